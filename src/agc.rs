@@ -0,0 +1,403 @@
+//! Automatic gain control driven by the TDC1000 error flags.
+//!
+//! Weak or missing echoes raise the PGA gain and, once the gain limit is
+//! reached, lower the echo qualification threshold. A signal that exceeds the
+//! largest threshold steps the other way round. A step is only taken after
+//! the same condition was reported for several consecutive measurements.
+
+use crate::trace::SpiTrace;
+use crate::{
+    ConfigAddresses, EchoQualificationThreshold, ErrNoSignalRead,
+    ErrSignalHighRead, ErrSignalWeakRead, Error, ErrorFlagsRead, PgaGain,
+    Tdc1000, ECHO_THRESHOLDS, PGA_GAINS,
+};
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AgcState {
+    Searching,
+    Locked,
+    /// The signal is still out of range but gain and threshold are at their
+    /// limits.
+    AtLimit,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AgcStep {
+    Unchanged,
+    GainIncreased,
+    GainDecreased,
+    ThresholdIncreased,
+    ThresholdDecreased,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SignalLevel {
+    Ok,
+    Weak,
+    High,
+}
+
+pub struct AgcController {
+    min_gain: PgaGain,
+    max_gain: PgaGain,
+    min_threshold: EchoQualificationThreshold,
+    max_threshold: EchoQualificationThreshold,
+    hysteresis: u8,
+    locked_hysteresis: u8,
+    lock_count: u8,
+    state: AgcState,
+    last_level: SignalLevel,
+    level_count: u8,
+}
+
+impl Default for AgcController {
+    fn default() -> Self {
+        AgcController {
+            min_gain: PgaGain::DB0,
+            max_gain: PgaGain::DB21,
+            min_threshold: EchoQualificationThreshold::Mv35,
+            max_threshold: EchoQualificationThreshold::Mv1500,
+            hysteresis: 2,
+            locked_hysteresis: 4,
+            lock_count: 8,
+            state: AgcState::Searching,
+            last_level: SignalLevel::Ok,
+            level_count: 0,
+        }
+    }
+}
+
+impl AgcController {
+    /// Limits are swapped into order if `min` exceeds `max`.
+    pub fn set_gain_limits(&mut self, min: PgaGain, max: PgaGain) {
+        if (min as u8) <= max as u8 {
+            self.min_gain = min;
+            self.max_gain = max;
+        } else {
+            self.min_gain = max;
+            self.max_gain = min;
+        }
+    }
+
+    /// Limits are swapped into order if `min` exceeds `max`.
+    pub fn set_threshold_limits(
+        &mut self,
+        min: EchoQualificationThreshold,
+        max: EchoQualificationThreshold,
+    ) {
+        if (min as u8) <= max as u8 {
+            self.min_threshold = min;
+            self.max_threshold = max;
+        } else {
+            self.min_threshold = max;
+            self.max_threshold = min;
+        }
+    }
+
+    /// Consecutive out of range measurements required for a step while
+    /// searching.
+    pub fn set_hysteresis(&mut self, measurements: u8) {
+        self.hysteresis = measurements.max(1);
+    }
+
+    /// Consecutive out of range measurements required to leave the locked
+    /// state.
+    pub fn set_locked_hysteresis(&mut self, measurements: u8) {
+        self.locked_hysteresis = measurements.max(1);
+    }
+
+    /// Consecutive good measurements required to enter the locked state.
+    pub fn set_lock_count(&mut self, measurements: u8) {
+        self.lock_count = measurements.max(1);
+    }
+
+    pub fn state(&self) -> AgcState {
+        self.state
+    }
+
+    /// Feeds the error flags of one measurement into the controller and
+    /// updates gain or threshold of `tdc1000` if a step is due.
+    pub fn update<T: SpiTrace>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        error_flags: &ErrorFlagsRead,
+    ) -> AgcStep {
+        let level = SignalLevel::from_error_flags(error_flags);
+        if level == self.last_level {
+            self.level_count = self.level_count.saturating_add(1);
+        } else {
+            self.last_level = level;
+            self.level_count = 1;
+        }
+
+        if level == SignalLevel::Ok {
+            if self.level_count >= self.lock_count {
+                self.state = AgcState::Locked;
+            }
+            return AgcStep::Unchanged;
+        }
+
+        let required = match self.state {
+            AgcState::Locked => self.locked_hysteresis,
+            _ => self.hysteresis,
+        };
+        if self.level_count < required {
+            return AgcStep::Unchanged;
+        }
+        self.level_count = 0;
+
+        let step = match level {
+            SignalLevel::Weak => self.raise_sensitivity(tdc1000),
+            _ => self.lower_sensitivity(tdc1000),
+        };
+        self.state = match step {
+            AgcStep::Unchanged => AgcState::AtLimit,
+            _ => AgcState::Searching,
+        };
+        step
+    }
+
+    /// Like [`update`](Self::update) but also writes the changed TOF1 or
+    /// CONFIG3 register to the device.
+    pub fn run<T, CS, SPI, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        error_flags: &ErrorFlagsRead,
+    ) -> Result<AgcStep, Error<CsE, SpiE>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        let step = self.update(tdc1000, error_flags);
+        match step {
+            AgcStep::GainIncreased | AgcStep::GainDecreased => {
+                tdc1000.write_register(cs, spi, ConfigAddresses::Tof1)?
+            }
+            AgcStep::ThresholdIncreased | AgcStep::ThresholdDecreased => {
+                tdc1000.write_register(cs, spi, ConfigAddresses::Config3)?
+            }
+            AgcStep::Unchanged => {}
+        }
+        Ok(step)
+    }
+
+    fn raise_sensitivity<T: SpiTrace>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+    ) -> AgcStep {
+        let gain = tdc1000.amplifier_and_time_of_flight.pga_gain as u8;
+        let threshold = tdc1000.config3.echo_qualification_threshold as u8;
+        if gain < self.max_gain as u8 {
+            tdc1000.set_pga_gain(PGA_GAINS[gain as usize + 1]);
+            AgcStep::GainIncreased
+        } else if threshold > self.min_threshold as u8 {
+            tdc1000.set_echo_qualification_threshold(
+                ECHO_THRESHOLDS[threshold as usize - 1],
+            );
+            AgcStep::ThresholdDecreased
+        } else {
+            AgcStep::Unchanged
+        }
+    }
+
+    fn lower_sensitivity<T: SpiTrace>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+    ) -> AgcStep {
+        let gain = tdc1000.amplifier_and_time_of_flight.pga_gain as u8;
+        let threshold = tdc1000.config3.echo_qualification_threshold as u8;
+        if gain > self.min_gain as u8 {
+            tdc1000.set_pga_gain(PGA_GAINS[gain as usize - 1]);
+            AgcStep::GainDecreased
+        } else if threshold < self.max_threshold as u8 {
+            tdc1000.set_echo_qualification_threshold(
+                ECHO_THRESHOLDS[threshold as usize + 1],
+            );
+            AgcStep::ThresholdIncreased
+        } else {
+            AgcStep::Unchanged
+        }
+    }
+}
+
+impl SignalLevel {
+    fn from_error_flags(error_flags: &ErrorFlagsRead) -> Self {
+        if *error_flags.signal_high() == ErrSignalHighRead::SignalHigh {
+            SignalLevel::High
+        } else if *error_flags.signal_week()
+            == ErrSignalWeakRead::SignalWeekTimeout
+            || *error_flags.no_signal() == ErrNoSignalRead::NoSignalTimeout
+        {
+            SignalLevel::Weak
+        } else {
+            SignalLevel::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::agc::{AgcController, AgcState, AgcStep};
+    use crate::simulator::{Simulator, Transaction};
+    use crate::{
+        EchoQualificationThreshold, ErrNoSignalRead, ErrSignalHighRead,
+        ErrorFlagsRead, PgaGain, Tdc1000,
+    };
+
+    fn no_signal() -> ErrorFlagsRead {
+        ErrorFlagsRead {
+            no_signal: ErrNoSignalRead::NoSignalTimeout,
+            ..ErrorFlagsRead::default()
+        }
+    }
+
+    fn signal_high() -> ErrorFlagsRead {
+        ErrorFlagsRead {
+            signal_high: ErrSignalHighRead::SignalHigh,
+            ..ErrorFlagsRead::default()
+        }
+    }
+
+    #[test]
+    fn gain_is_raised_after_hysteresis_and_threshold_at_gain_limit() {
+        let mut tdc1000 = Tdc1000::default();
+        let mut agc = AgcController::default();
+        agc.set_gain_limits(PgaGain::DB0, PgaGain::DB3);
+
+        assert_eq!(agc.update(&mut tdc1000, &no_signal()), AgcStep::Unchanged);
+        assert_eq!(
+            agc.update(&mut tdc1000, &no_signal()),
+            AgcStep::GainIncreased
+        );
+        assert_eq!(tdc1000.get_tof_1_value() >> 5, PgaGain::DB3 as u8);
+
+        agc.update(&mut tdc1000, &no_signal());
+        assert_eq!(
+            agc.update(&mut tdc1000, &no_signal()),
+            AgcStep::ThresholdDecreased
+        );
+        assert_eq!(
+            tdc1000.get_config_3_value() & 0b111,
+            EchoQualificationThreshold::Mv75 as u8
+        );
+    }
+
+    #[test]
+    fn limits_are_reported_when_sensitivity_cannot_be_lowered() {
+        let mut tdc1000 = Tdc1000::default();
+        let mut agc = AgcController::default();
+        agc.set_hysteresis(1);
+        agc.set_threshold_limits(
+            EchoQualificationThreshold::Mv35,
+            EchoQualificationThreshold::Mv125,
+        );
+        assert_eq!(
+            agc.update(&mut tdc1000, &signal_high()),
+            AgcStep::Unchanged
+        );
+        assert_eq!(agc.state(), AgcState::AtLimit);
+    }
+
+    #[test]
+    fn swapped_limits_are_put_in_order() {
+        let mut tdc1000 = Tdc1000::default();
+        let mut agc = AgcController::default();
+        agc.set_hysteresis(1);
+        agc.set_gain_limits(PgaGain::DB3, PgaGain::DB0);
+        agc.set_threshold_limits(
+            EchoQualificationThreshold::Mv125,
+            EchoQualificationThreshold::Mv75,
+        );
+        assert_eq!(
+            agc.update(&mut tdc1000, &no_signal()),
+            AgcStep::GainIncreased
+        );
+        assert_eq!(
+            agc.update(&mut tdc1000, &no_signal()),
+            AgcStep::ThresholdDecreased
+        );
+        assert_eq!(agc.update(&mut tdc1000, &no_signal()), AgcStep::Unchanged);
+        assert_eq!(tdc1000.get_tof_1_value() >> 5, PgaGain::DB3 as u8);
+        assert_eq!(
+            tdc1000.get_config_3_value() & 0b111,
+            EchoQualificationThreshold::Mv75 as u8
+        );
+    }
+
+    #[test]
+    fn run_writes_only_the_changed_register() {
+        let simulator = Simulator::new();
+        let mut cs = simulator.cs();
+        let mut spi = simulator.spi();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.write_settings(&mut cs, &mut spi).unwrap();
+        let mut agc = AgcController::default();
+        agc.set_hysteresis(1);
+        agc.set_gain_limits(PgaGain::DB0, PgaGain::DB3);
+        let written = |simulator: &Simulator| {
+            (0..simulator.transaction_count())
+                .filter_map(|index| match simulator.transaction(index) {
+                    Some(Transaction::Write { address, .. }) => Some(address),
+                    _ => None,
+                })
+                .collect::<std::vec::Vec<_>>()
+        };
+
+        simulator.clear_log();
+        let step = agc.run(&mut tdc1000, &mut cs, &mut spi, &no_signal());
+        assert_eq!(step, Ok(AgcStep::GainIncreased));
+        assert_eq!(written(&simulator), [5]);
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+
+        simulator.clear_log();
+        let step = agc.run(&mut tdc1000, &mut cs, &mut spi, &no_signal());
+        assert_eq!(step, Ok(AgcStep::ThresholdDecreased));
+        assert_eq!(written(&simulator), [3]);
+        assert_eq!(simulator.register(3), tdc1000.get_config_3_value());
+
+        simulator.clear_log();
+        let step = agc.run(
+            &mut tdc1000,
+            &mut cs,
+            &mut spi,
+            &ErrorFlagsRead::default(),
+        );
+        assert_eq!(step, Ok(AgcStep::Unchanged));
+        assert_eq!(simulator.transaction_count(), 0);
+    }
+
+    #[test]
+    fn controller_locks_and_needs_more_errors_to_unlock() {
+        let mut tdc1000 = Tdc1000::default();
+        let mut agc = AgcController::default();
+        agc.set_lock_count(3);
+        for _ in 0..3 {
+            agc.update(&mut tdc1000, &ErrorFlagsRead::default());
+        }
+        assert_eq!(agc.state(), AgcState::Locked);
+
+        for _ in 0..3 {
+            assert_eq!(
+                agc.update(&mut tdc1000, &no_signal()),
+                AgcStep::Unchanged
+            );
+        }
+        assert_eq!(
+            agc.update(&mut tdc1000, &no_signal()),
+            AgcStep::GainIncreased
+        );
+        assert_eq!(agc.state(), AgcState::Searching);
+    }
+}
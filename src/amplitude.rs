@@ -0,0 +1,324 @@
+//! Echo amplitude estimation by sweeping the echo qualification threshold.
+//!
+//! The TDC1000 has no ADC, but it reports a weak or missing echo as soon as
+//! the echo qualification threshold exceeds the amplified echo. Bracketing
+//! the echo between two thresholds and dividing by the receive gain gives a
+//! coarse estimate of the peak amplitude at the receiver input.
+
+use crate::measurement::{MeasureError, TofCapture};
+use crate::trace::SpiTrace;
+use crate::{
+    AmplifierControl, ConfigAddresses, ErrNoSignalRead, ErrSignalWeakRead,
+    PgaGain, Tdc1000, ECHO_THRESHOLDS,
+};
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+const LNA_GAIN_DB: f32 = 20.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AmplitudeEstimate {
+    /// Largest passed threshold referred to the receiver input in mV.
+    pub lower: f32,
+    /// Smallest failed threshold referred to the receiver input in mV, `None`
+    /// if the echo passed all thresholds.
+    pub upper: Option<f32>,
+}
+
+impl AmplitudeEstimate {
+    /// Estimated peak amplitude at the receiver input in mV.
+    pub fn millivolts(&self) -> f32 {
+        match self.upper {
+            Some(upper) => libm::sqrtf(self.lower * upper),
+            None => self.lower,
+        }
+    }
+}
+
+pub struct AmplitudeSweep<'a> {
+    gains: &'a [PgaGain],
+    measurements_per_threshold: u16,
+    pass_ratio: f32,
+}
+
+impl<'a> AmplitudeSweep<'a> {
+    /// The sweep is repeated for each of the `gains` until the echo is
+    /// bracketed by two thresholds, so they should be listed from low to high
+    /// gain. An empty slice keeps the configured PGA gain. At least one
+    /// measurement is taken per threshold.
+    pub fn new(gains: &'a [PgaGain], measurements_per_threshold: u16) -> Self {
+        AmplitudeSweep {
+            gains,
+            measurements_per_threshold: measurements_per_threshold.max(1),
+            pass_ratio: 0.5,
+        }
+    }
+
+    /// Share of measurements that must qualify an echo for a threshold to
+    /// count as passed.
+    pub fn set_pass_ratio(&mut self, pass_ratio: f32) {
+        self.pass_ratio = pass_ratio;
+    }
+
+    /// Runs the sweep and restores the original gain and threshold
+    /// afterwards, also if the sweep fails. Returns `None` if no echo was
+    /// qualified at any gain.
+    pub fn run<T, CS, SPI, C, CsE, SpiE>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+    ) -> Result<Option<AmplitudeEstimate>, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let original_gain = tdc1000.amplifier_and_time_of_flight.pga_gain;
+        let original_threshold = tdc1000.config3.echo_qualification_threshold;
+        let result = self.sweep(tdc1000, cs, spi, capture);
+        tdc1000.set_pga_gain(original_gain);
+        tdc1000.set_echo_qualification_threshold(original_threshold);
+        let restored = tdc1000
+            .write_register(cs, spi, ConfigAddresses::Tof1)
+            .and_then(|()| {
+                tdc1000.write_register(cs, spi, ConfigAddresses::Config3)
+            });
+        // The sweep error is more useful than a failed restore.
+        let estimate = result?;
+        restored?;
+        Ok(estimate)
+    }
+
+    fn sweep<T, CS, SPI, C, CsE, SpiE>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+    ) -> Result<Option<AmplitudeEstimate>, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let current_gain = [tdc1000.amplifier_and_time_of_flight.pga_gain];
+        let gains = if self.gains.is_empty() {
+            &current_gain[..]
+        } else {
+            self.gains
+        };
+
+        let mut estimate = None;
+        for gain in gains {
+            tdc1000.set_pga_gain(*gain);
+            tdc1000.write_register(cs, spi, ConfigAddresses::Tof1)?;
+            let gain = input_gain(tdc1000);
+            let mut passed = None;
+            let mut failed = None;
+            for threshold in ECHO_THRESHOLDS.iter() {
+                tdc1000.set_echo_qualification_threshold(*threshold);
+                tdc1000.write_register(cs, spi, ConfigAddresses::Config3)?;
+                if self.passes(tdc1000, cs, spi, capture)? {
+                    passed = Some(threshold.millivolts() as f32 / gain);
+                } else {
+                    failed = Some(threshold.millivolts() as f32 / gain);
+                    break;
+                }
+            }
+            match (passed, failed) {
+                (Some(lower), Some(upper)) => {
+                    estimate = Some(AmplitudeEstimate {
+                        lower,
+                        upper: Some(upper),
+                    });
+                    break;
+                }
+                (Some(lower), None) => {
+                    let is_larger = match estimate {
+                        Some(previous) => lower > previous.lower,
+                        None => true,
+                    };
+                    if is_larger {
+                        estimate =
+                            Some(AmplitudeEstimate { lower, upper: None });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(estimate)
+    }
+
+    fn passes<T, CS, SPI, C, CsE, SpiE>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+    ) -> Result<bool, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let mut qualified = 0;
+        for _ in 0..self.measurements_per_threshold {
+            let measurement = tdc1000.measure(cs, spi, capture)?;
+            let error_flags =
+                measurement.error_flags().copied().unwrap_or_default();
+            if !measurement.is_timeout()
+                && *error_flags.signal_week() == ErrSignalWeakRead::NoError
+                && *error_flags.no_signal() == ErrNoSignalRead::NoError
+            {
+                qualified += 1;
+            }
+        }
+        Ok(qualified as f32
+            >= self.pass_ratio * self.measurements_per_threshold as f32)
+    }
+}
+
+/// Linear voltage gain between receiver input and echo comparator.
+fn input_gain<T>(tdc1000: &Tdc1000<T>) -> f32 {
+    let amplifier = &tdc1000.amplifier_and_time_of_flight;
+    let mut gain_db = 0.0;
+    if let AmplifierControl::Active = amplifier.lna_ctrl {
+        gain_db += LNA_GAIN_DB;
+    }
+    if let AmplifierControl::Active = amplifier.pga_ctrl {
+        gain_db += amplifier.pga_gain.decibels() as f32;
+    }
+    libm::powf(10.0, gain_db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::amplitude::AmplitudeSweep;
+    use crate::measurement::MeasureError;
+    use crate::test_support::{
+        FailingCapture, FakeCapture, FakeCs, FakeFrontEnd, FakeSpi,
+    };
+    use crate::{
+        EchoQualificationThreshold, PgaGain, Tdc1000, ECHO_THRESHOLDS,
+    };
+
+    fn echo(registers: &[u8; 10], input_mv: f32) -> (Option<f32>, u8) {
+        let gain_db = 20.0 + (registers[5] >> 5) as f32 * 3.0;
+        let amplitude = input_mv * libm::powf(10.0, gain_db / 20.0);
+        let threshold = ECHO_THRESHOLDS[(registers[3] & 0b111) as usize];
+        if amplitude < threshold.millivolts() as f32 {
+            (None, 0b10)
+        } else if amplitude > 1500.0 {
+            (Some(1e-4), 0b1)
+        } else {
+            (Some(1e-4), 0)
+        }
+    }
+
+    #[test]
+    fn amplitude_is_bracketed_by_thresholds() {
+        let front_end = FakeFrontEnd::new(|registers, _| echo(registers, 10.0));
+        let mut tdc1000 = Tdc1000::default();
+        let estimate = AmplitudeSweep::new(&[], 3)
+            .run(
+                &mut tdc1000,
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+            )
+            .unwrap()
+            .unwrap();
+        assert!((estimate.lower - 7.5).abs() < 0.01);
+        assert!((estimate.upper.unwrap() - 12.5).abs() < 0.01);
+        assert!((estimate.millivolts() - 9.68).abs() < 0.01);
+        assert_eq!(
+            front_end.borrow().registers[3],
+            tdc1000.get_config_3_value()
+        );
+    }
+
+    #[test]
+    fn gain_is_raised_for_weak_echoes() {
+        let front_end = FakeFrontEnd::new(|registers, _| echo(registers, 0.5));
+        let mut tdc1000 = Tdc1000::default();
+        let estimate = AmplitudeSweep::new(&[PgaGain::DB0, PgaGain::DB21], 3)
+            .run(
+                &mut tdc1000,
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+            )
+            .unwrap()
+            .unwrap();
+        assert!(estimate.lower < 0.5 && estimate.upper.unwrap() > 0.5);
+        assert_eq!(front_end.borrow().registers[5] >> 5, PgaGain::DB0 as u8);
+    }
+
+    #[test]
+    fn missing_echo_gives_no_estimate() {
+        let front_end = FakeFrontEnd::new(|_, _| (None, 0b10));
+        let mut tdc1000 = Tdc1000::default();
+        let estimate = AmplitudeSweep::new(&[], 3)
+            .run(
+                &mut tdc1000,
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+            )
+            .unwrap();
+        assert!(estimate.is_none());
+    }
+
+    #[test]
+    fn failed_sweep_restores_gain_and_threshold() {
+        let front_end = FakeFrontEnd::new(|registers, _| echo(registers, 10.0));
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB12);
+        tdc1000.set_echo_qualification_threshold(
+            EchoQualificationThreshold::Mv410,
+        );
+        let result = AmplitudeSweep::new(&[PgaGain::DB0], 3).run(
+            &mut tdc1000,
+            &mut FakeCs,
+            &mut FakeSpi(&front_end),
+            &mut FailingCapture {
+                capture: FakeCapture(&front_end),
+                remaining: 4,
+            },
+        );
+        assert_eq!(result, Err(MeasureError::CaptureError(())));
+        let registers = front_end.borrow().registers;
+        assert_eq!(registers[5] >> 5, PgaGain::DB12 as u8);
+        assert_eq!(registers[3], tdc1000.get_config_3_value());
+        assert_eq!(
+            tdc1000.config3.echo_qualification_threshold,
+            EchoQualificationThreshold::Mv410
+        );
+    }
+
+    #[test]
+    fn zero_measurements_take_one_per_threshold() {
+        let front_end = FakeFrontEnd::new(|_, _| (None, 0b10));
+        let estimate = AmplitudeSweep::new(&[], 0)
+            .run(
+                &mut Tdc1000::default(),
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+            )
+            .unwrap();
+        assert!(estimate.is_none());
+        assert_eq!(front_end.borrow().measurements, 1);
+    }
+}
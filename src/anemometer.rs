@@ -0,0 +1,323 @@
+//! Two-axis ultrasonic anemometer.
+//!
+//! Each axis consists of a transducer pair connected to channel 1 and
+//! channel 2 of a TDC1000 running in TOF mode 2 with channel swap enabled, so
+//! consecutive measurements alternate between the forward (channel 1 to
+//! channel 2) and the reverse direction. After `write_settings` the first
+//! measurement is taken on the selected channel, which should be channel 1.
+//!
+//! Wind speed and speed of sound are calculated with the reciprocal TOF
+//! method: `v = L / 2 * (1 / t_fwd - 1 / t_rev)` and
+//! `c = L / 2 * (1 / t_fwd + 1 / t_rev)`, so the wind speed does not depend on
+//! the air temperature.
+
+use crate::measurement::{TofCapture, TofMeasurement};
+use crate::ErrorFlagsRead;
+
+/// `gamma * R / M` of dry air in m²/(s²·K).
+const DRY_AIR_SONIC_CONSTANT: f32 = 401.87;
+const KELVIN_OFFSET: f32 = 273.15;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AnemometerError<E> {
+    CaptureError(E),
+    NoEcho,
+    /// The TOF is zero, negative or not finite.
+    InvalidTof,
+    /// The capture attached error flags to the measurement.
+    SignalError(ErrorFlagsRead),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AxisTof {
+    /// TOF from channel 1 to channel 2 in seconds.
+    pub forward: f32,
+    /// TOF from channel 2 to channel 1 in seconds.
+    pub reverse: f32,
+}
+
+impl AxisTof {
+    /// Wind speed component in m/s pointing from channel 1 to channel 2.
+    pub fn wind_speed(&self, path_length: f32) -> f32 {
+        path_length / 2.0 * (1.0 / self.forward - 1.0 / self.reverse)
+    }
+
+    pub fn speed_of_sound(&self, path_length: f32) -> f32 {
+        path_length / 2.0 * (1.0 / self.forward + 1.0 / self.reverse)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Wind {
+    /// Wind speed component along the x axis in m/s.
+    pub speed_x: f32,
+    /// Wind speed component along the y axis in m/s.
+    pub speed_y: f32,
+    /// Speed of sound averaged over both axes in m/s.
+    pub speed_of_sound: f32,
+}
+
+impl Wind {
+    pub fn speed(&self) -> f32 {
+        libm::sqrtf(self.speed_x * self.speed_x + self.speed_y * self.speed_y)
+    }
+
+    /// Direction the wind is blowing towards in degrees, counted from the
+    /// x axis towards the y axis in the range 0..360.
+    pub fn direction(&self) -> f32 {
+        let direction = libm::atan2f(self.speed_y, self.speed_x).to_degrees();
+        if direction < 0.0 {
+            direction + 360.0
+        } else {
+            direction
+        }
+    }
+
+    /// Sonic temperature in °C, assuming dry air.
+    pub fn sonic_temperature(&self) -> f32 {
+        sonic_temperature(self.speed_of_sound)
+    }
+}
+
+pub fn sonic_temperature(speed_of_sound: f32) -> f32 {
+    speed_of_sound * speed_of_sound / DRY_AIR_SONIC_CONSTANT - KELVIN_OFFSET
+}
+
+pub struct Anemometer {
+    x_path_length: f32,
+    y_path_length: f32,
+}
+
+impl Anemometer {
+    /// Path lengths between the transducers of each axis in metres.
+    pub fn new(x_path_length: f32, y_path_length: f32) -> Self {
+        Anemometer {
+            x_path_length,
+            y_path_length,
+        }
+    }
+
+    /// Takes the forward and the reverse measurement. Both are taken even if
+    /// the forward one fails, so the channel swap of the device stays in step
+    /// with the next call. Measurements without an echo, with error flags or
+    /// with a TOF that is not a positive finite value are rejected.
+    pub fn measure_axis<C>(
+        capture: &mut C,
+    ) -> Result<AxisTof, AnemometerError<C::Error>>
+    where
+        C: TofCapture,
+    {
+        let forward = capture.capture();
+        let reverse = capture.capture();
+        Ok(AxisTof {
+            forward: Self::first_stop(forward)?,
+            reverse: Self::first_stop(reverse)?,
+        })
+    }
+
+    /// Measures both axes. With a single TDC1000 and an external
+    /// multiplexer `x` and `y` may refer to the same capture type with
+    /// different multiplexer settings.
+    pub fn measure<C>(
+        &self,
+        x: &mut C,
+        y: &mut C,
+    ) -> Result<Wind, AnemometerError<C::Error>>
+    where
+        C: TofCapture,
+    {
+        let x_tof = Self::measure_axis(x)?;
+        let y_tof = Self::measure_axis(y)?;
+        Ok(self.wind(&x_tof, &y_tof))
+    }
+
+    pub fn wind(&self, x: &AxisTof, y: &AxisTof) -> Wind {
+        let speed_of_sound = (x.speed_of_sound(self.x_path_length)
+            + y.speed_of_sound(self.y_path_length))
+            / 2.0;
+        Wind {
+            speed_x: x.wind_speed(self.x_path_length),
+            speed_y: y.wind_speed(self.y_path_length),
+            speed_of_sound,
+        }
+    }
+
+    /// The first STOP of `measurement` if it can be used for the wind
+    /// calculation, which divides by it.
+    fn first_stop<E>(
+        measurement: Result<TofMeasurement, E>,
+    ) -> Result<f32, AnemometerError<E>> {
+        let measurement = measurement.map_err(AnemometerError::CaptureError)?;
+        if let Some(error_flags) = measurement.error_flags() {
+            return Err(AnemometerError::SignalError(*error_flags));
+        }
+        let tof = measurement.first_stop().ok_or(AnemometerError::NoEcho)?;
+        if !tof.is_finite() || tof <= 0.0 {
+            return Err(AnemometerError::InvalidTof);
+        }
+        Ok(tof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::anemometer::{Anemometer, AnemometerError, AxisTof};
+    use crate::measurement::{TofCapture, TofMeasurement};
+    use crate::{ErrSignalWeakRead, ErrorFlagsRead};
+
+    const PATH_LENGTH: f32 = 0.2;
+
+    /// Returns `tofs` in order, failing on the capture at index `failure`.
+    struct SequenceCapture<'a> {
+        tofs: &'a [Option<f32>],
+        index: usize,
+        failure: Option<usize>,
+    }
+
+    impl<'a> SequenceCapture<'a> {
+        fn new(tofs: &'a [Option<f32>]) -> Self {
+            SequenceCapture {
+                tofs,
+                index: 0,
+                failure: None,
+            }
+        }
+    }
+
+    impl TofCapture for SequenceCapture<'_> {
+        type Error = ();
+
+        fn capture(&mut self) -> Result<TofMeasurement, ()> {
+            let tof = self.tofs[self.index];
+            self.index += 1;
+            if self.failure == Some(self.index - 1) {
+                return Err(());
+            }
+            Ok(match tof {
+                Some(tof) => TofMeasurement::new(&[tof]),
+                None => TofMeasurement::timeout(),
+            })
+        }
+    }
+
+    fn axis_tof(wind_speed: f32, speed_of_sound: f32) -> AxisTof {
+        AxisTof {
+            forward: PATH_LENGTH / (speed_of_sound + wind_speed),
+            reverse: PATH_LENGTH / (speed_of_sound - wind_speed),
+        }
+    }
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{} is not close to {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn wind_speed_and_speed_of_sound_are_recovered_from_tof() {
+        let tof = axis_tof(3.0, 343.0);
+        assert_close(tof.wind_speed(PATH_LENGTH), 3.0, 1e-3);
+        assert_close(tof.speed_of_sound(PATH_LENGTH), 343.0, 1e-2);
+    }
+
+    #[test]
+    fn wind_direction_and_sonic_temperature_are_calculated() {
+        let anemometer = Anemometer::new(PATH_LENGTH, PATH_LENGTH);
+        let wind =
+            anemometer.wind(&axis_tof(-2.0, 343.2), &axis_tof(-2.0, 343.2));
+        assert_close(wind.speed(), 2.828, 1e-2);
+        assert_close(wind.direction(), 225.0, 0.1);
+        assert_close(wind.sonic_temperature(), 20.0, 0.3);
+    }
+
+    #[test]
+    fn both_axes_are_measured_with_alternating_directions() {
+        let x = axis_tof(1.0, 340.0);
+        let y = axis_tof(-4.0, 340.0);
+        let x_tofs = [Some(x.forward), Some(x.reverse)];
+        let y_tofs = [Some(y.forward), Some(y.reverse)];
+        let mut x_capture = SequenceCapture::new(&x_tofs);
+        let mut y_capture = SequenceCapture::new(&y_tofs);
+        let anemometer = Anemometer::new(PATH_LENGTH, PATH_LENGTH);
+        let wind = anemometer.measure(&mut x_capture, &mut y_capture).unwrap();
+        assert_close(wind.speed_x, 1.0, 1e-3);
+        assert_close(wind.speed_y, -4.0, 1e-3);
+        assert_close(wind.speed_of_sound, 340.0, 1e-2);
+    }
+
+    #[test]
+    fn missing_echo_is_reported() {
+        let tofs = [Some(5.8e-4), None];
+        let mut capture = SequenceCapture::new(&tofs);
+        assert!(matches!(
+            Anemometer::measure_axis(&mut capture),
+            Err(AnemometerError::NoEcho)
+        ));
+    }
+
+    #[test]
+    fn unusable_tofs_are_rejected() {
+        for tof in [0.0, -1e-4, f32::INFINITY, f32::NAN].iter() {
+            let tofs = [Some(5.8e-4), Some(*tof)];
+            let mut capture = SequenceCapture::new(&tofs);
+            assert!(matches!(
+                Anemometer::measure_axis(&mut capture),
+                Err(AnemometerError::InvalidTof)
+            ));
+        }
+    }
+
+    #[test]
+    fn measurements_with_error_flags_are_rejected() {
+        struct WeakCapture;
+
+        impl TofCapture for WeakCapture {
+            type Error = ();
+
+            fn capture(&mut self) -> Result<TofMeasurement, ()> {
+                let mut measurement = TofMeasurement::new(&[5.8e-4]);
+                measurement.record_error(ErrorFlagsRead {
+                    signal_week: ErrSignalWeakRead::SignalWeekTimeout,
+                    ..ErrorFlagsRead::default()
+                });
+                Ok(measurement)
+            }
+        }
+
+        match Anemometer::measure_axis(&mut WeakCapture) {
+            Err(AnemometerError::SignalError(error_flags)) => assert_eq!(
+                *error_flags.signal_week(),
+                ErrSignalWeakRead::SignalWeekTimeout
+            ),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn failed_forward_capture_keeps_directions_in_step() {
+        let tof = axis_tof(2.0, 340.0);
+        let tofs = [
+            Some(tof.forward),
+            Some(tof.reverse),
+            Some(tof.forward),
+            Some(tof.reverse),
+        ];
+        let mut capture = SequenceCapture::new(&tofs);
+        capture.failure = Some(0);
+        assert!(matches!(
+            Anemometer::measure_axis(&mut capture),
+            Err(AnemometerError::CaptureError(()))
+        ));
+        let tof = Anemometer::measure_axis(&mut capture).unwrap();
+        assert_close(tof.wind_speed(PATH_LENGTH), 2.0, 1e-3);
+    }
+}
@@ -0,0 +1,396 @@
+//! Const configuration builder.
+//!
+//! [`Tdc1000Config`] mirrors the setters of [`Tdc1000`] as `const fn`s, so a
+//! front-end profile can be a `const` item whose register image is computed
+//! at compile time:
+//!
+//! ```
+//! use tdc1000::config::Tdc1000Config;
+//! use tdc1000::{PgaGain, TxFrequencyDivider, TxPulses};
+//!
+//! const WATER: Tdc1000Config = Tdc1000Config::new()
+//!     .tx_divider(TxFrequencyDivider::DivideBy8)
+//!     .pulses(TxPulses::new_const(10))
+//!     .pga_gain(PgaGain::DB12);
+//! const WATER_REGISTERS: [u8; 9] = WATER.register_image();
+//! const _: () = assert!(WATER_REGISTERS[0] == 0x4a);
+//!
+//! let tdc1000 = WATER.build();
+//! assert_eq!(tdc1000.get_tof_1_value(), WATER_REGISTERS[5]);
+//! ```
+
+pub mod lint;
+pub mod presets;
+
+use crate::trace::NoTrace;
+use crate::{
+    AmplifierAndTimeOfFlight, AmplifierControl, AutoZeroPeriod, ChannelSelect,
+    ChannelSwap, ClockInDiv, ClockRate, Config0, Config1, Config2, Config3,
+    Config4, DampingMode, EchoQualificationThreshold, EchoTimeout,
+    ExternalChannelSelect, ForceShortTimeOfFlight, LnaFeedbackMode,
+    MeasurementCycles, MeasurementMode, PgaGain, PowerBlanking,
+    ReceiveEventsCnt, ReceiveMode, ShortTofBlankPeriod, TOFMeasurementMode,
+    Tdc1000, TempClockDivider, TempMode, TempRtdSelect, TimeOfFlightValue,
+    TimeOut, TofTimeoutControl, TriggerEdgePolarity, TxFrequencyDivider,
+    TxPulseShiftPosition, TxPulses, VoltageReference, ALL_REGISTERS_DIRTY,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tdc1000Config {
+    pub(crate) config0: Config0,
+    pub(crate) config1: Config1,
+    pub(crate) config2: Config2,
+    pub(crate) config3: Config3,
+    pub(crate) config4: Config4,
+    pub(crate) amplifier_and_time_of_flight: AmplifierAndTimeOfFlight,
+    pub(crate) timeout: TimeOut,
+    pub(crate) clock_rate: ClockRate,
+}
+
+impl Default for Tdc1000Config {
+    fn default() -> Self {
+        Tdc1000Config::new()
+    }
+}
+
+impl Tdc1000Config {
+    /// Starts from the same settings as [`Tdc1000::default`].
+    pub const fn new() -> Self {
+        Tdc1000Config {
+            config0: Config0 {
+                tx_frequency_divider: TxFrequencyDivider::DivideBy8,
+                tx_pulses: TxPulses::new_const(5),
+            },
+            config1: Config1 {
+                measurement_cycles: MeasurementCycles::MeasurementCycles1,
+                receive_events_cnt: ReceiveEventsCnt::DoNotCountStopEvents,
+            },
+            config2: Config2 {
+                voltage_reference: VoltageReference::Internal,
+                measurement_mode: MeasurementMode::TimeOfFlight,
+                damping_mode: DampingMode::DisableDamping,
+                channel_swap: ChannelSwap::DisableSwap,
+                ext_channel_select:
+                    ExternalChannelSelect::DisableExternalChannelSelect,
+                channel_select: ChannelSelect::Channel1,
+                tof_meas_mode: TOFMeasurementMode::Mode0,
+            },
+            config3: Config3 {
+                temp_mode: TempMode::MeasureRefRtd1Rtd2,
+                temp_rtd: TempRtdSelect::PT1000,
+                temp_clk_div: TempClockDivider::DivideBy8,
+                blanking: PowerBlanking::DisablePowerBlanking,
+                echo_qualification_threshold: EchoQualificationThreshold::Mv125,
+            },
+            config4: Config4 {
+                receive_mode: ReceiveMode::SingleEcho,
+                trigger_edge_polarity: TriggerEdgePolarity::RisingEdge,
+                tx_pulse_shift_position: TxPulseShiftPosition::new_const(31),
+            },
+            amplifier_and_time_of_flight: AmplifierAndTimeOfFlight {
+                pga_gain: PgaGain::DB0,
+                pga_ctrl: AmplifierControl::Active,
+                lna_ctrl: AmplifierControl::Active,
+                lna_fb: LnaFeedbackMode::CapacitiveMode,
+                time_of_flight: TimeOfFlightValue::new_const(0),
+            },
+            timeout: TimeOut {
+                force_short_tof: ForceShortTimeOfFlight::Disabled,
+                short_tof_blank_period: ShortTofBlankPeriod::T0Times64,
+                echo_timeout: EchoTimeout::EnableTimeout,
+                tof_timeout_crl: TofTimeoutControl::T0Times256,
+            },
+            clock_rate: ClockRate {
+                clock_in_div: ClockInDiv::DivideBy1,
+                auto_zero_period: AutoZeroPeriod::T0Times64,
+            },
+        }
+    }
+
+    pub const fn tx_divider(mut self, divider: TxFrequencyDivider) -> Self {
+        self.config0.tx_frequency_divider = divider;
+        self
+    }
+
+    pub const fn pulses(mut self, pulses: TxPulses) -> Self {
+        self.config0.tx_pulses = pulses;
+        self
+    }
+
+    pub const fn measurement_cycles(
+        mut self,
+        cycles: MeasurementCycles,
+    ) -> Self {
+        self.config1.measurement_cycles = cycles;
+        self
+    }
+
+    pub const fn receive_events(
+        mut self,
+        events_cnt: ReceiveEventsCnt,
+    ) -> Self {
+        self.config1.receive_events_cnt = events_cnt;
+        self
+    }
+
+    pub const fn voltage_reference(
+        mut self,
+        voltage_reference: VoltageReference,
+    ) -> Self {
+        self.config2.voltage_reference = voltage_reference;
+        self
+    }
+
+    pub const fn measure_mode(mut self, measure_mode: MeasurementMode) -> Self {
+        self.config2.measurement_mode = measure_mode;
+        self
+    }
+
+    pub const fn damping(mut self, damping_mode: DampingMode) -> Self {
+        self.config2.damping_mode = damping_mode;
+        self
+    }
+
+    pub const fn channel_swap(mut self, channel_swap: ChannelSwap) -> Self {
+        self.config2.channel_swap = channel_swap;
+        self
+    }
+
+    pub const fn external_channel_select(
+        mut self,
+        external_channel_select: ExternalChannelSelect,
+    ) -> Self {
+        self.config2.ext_channel_select = external_channel_select;
+        self
+    }
+
+    pub const fn active_channel(mut self, channel: ChannelSelect) -> Self {
+        self.config2.channel_select = channel;
+        self
+    }
+
+    pub const fn tof_meas_mode(
+        mut self,
+        tof_measurement_mode: TOFMeasurementMode,
+    ) -> Self {
+        self.config2.tof_meas_mode = tof_measurement_mode;
+        self
+    }
+
+    pub const fn temp_measurement_mode(
+        mut self,
+        temp_measurement_mode: TempMode,
+    ) -> Self {
+        self.config3.temp_mode = temp_measurement_mode;
+        self
+    }
+
+    pub const fn temp_rtd_type(mut self, temp_rtd: TempRtdSelect) -> Self {
+        self.config3.temp_rtd = temp_rtd;
+        self
+    }
+
+    pub const fn temp_clock_divider(
+        mut self,
+        temp_clock_div: TempClockDivider,
+    ) -> Self {
+        self.config3.temp_clk_div = temp_clock_div;
+        self
+    }
+
+    pub const fn blanking(mut self, blanking: PowerBlanking) -> Self {
+        self.config3.blanking = blanking;
+        self
+    }
+
+    pub const fn echo_qualification_threshold(
+        mut self,
+        threshold: EchoQualificationThreshold,
+    ) -> Self {
+        self.config3.echo_qualification_threshold = threshold;
+        self
+    }
+
+    pub const fn receive_mode(mut self, receive_mode: ReceiveMode) -> Self {
+        self.config4.receive_mode = receive_mode;
+        self
+    }
+
+    pub const fn trigger(mut self, trigger: TriggerEdgePolarity) -> Self {
+        self.config4.trigger_edge_polarity = trigger;
+        self
+    }
+
+    pub const fn tx_pulse_shift_position(
+        mut self,
+        position: TxPulseShiftPosition,
+    ) -> Self {
+        self.config4.tx_pulse_shift_position = position;
+        self
+    }
+
+    pub const fn time_of_flight(mut self, tof: TimeOfFlightValue) -> Self {
+        self.amplifier_and_time_of_flight.time_of_flight = tof;
+        self
+    }
+
+    pub const fn pga_gain(mut self, gain: PgaGain) -> Self {
+        self.amplifier_and_time_of_flight.pga_gain = gain;
+        self
+    }
+
+    pub const fn pga_control(mut self, control: AmplifierControl) -> Self {
+        self.amplifier_and_time_of_flight.pga_ctrl = control;
+        self
+    }
+
+    pub const fn lna_control(mut self, control: AmplifierControl) -> Self {
+        self.amplifier_and_time_of_flight.lna_ctrl = control;
+        self
+    }
+
+    pub const fn lna_feedback_mode(
+        mut self,
+        feedback_mode: LnaFeedbackMode,
+    ) -> Self {
+        self.amplifier_and_time_of_flight.lna_fb = feedback_mode;
+        self
+    }
+
+    pub const fn force_short_tof(
+        mut self,
+        force_short_tof: ForceShortTimeOfFlight,
+    ) -> Self {
+        self.timeout.force_short_tof = force_short_tof;
+        self
+    }
+
+    pub const fn short_tof_blank_period(
+        mut self,
+        blank_period: ShortTofBlankPeriod,
+    ) -> Self {
+        self.timeout.short_tof_blank_period = blank_period;
+        self
+    }
+
+    pub const fn echo_timeout(mut self, timeout: EchoTimeout) -> Self {
+        self.timeout.echo_timeout = timeout;
+        self
+    }
+
+    pub const fn tof_timeout_ctrl(
+        mut self,
+        timeout_ctrl: TofTimeoutControl,
+    ) -> Self {
+        self.timeout.tof_timeout_crl = timeout_ctrl;
+        self
+    }
+
+    pub const fn clock_in_div(mut self, clock_in_div: ClockInDiv) -> Self {
+        self.clock_rate.clock_in_div = clock_in_div;
+        self
+    }
+
+    pub const fn auto_zero_period(
+        mut self,
+        auto_zero_period: AutoZeroPeriod,
+    ) -> Self {
+        self.clock_rate.auto_zero_period = auto_zero_period;
+        self
+    }
+
+    /// CONFIG_0..4, TOF_1, TOF_0, TIMEOUT and CLOCK_RATE in this order.
+    pub const fn register_image(&self) -> [u8; 9] {
+        [
+            self.config0.value(),
+            self.config1.value(),
+            self.config2.value(),
+            self.config3.value(),
+            self.config4.value(),
+            self.amplifier_and_time_of_flight.value(),
+            self.amplifier_and_time_of_flight
+                .time_of_flight
+                .get_8_low_bits_of_tof(),
+            self.timeout.value(),
+            self.clock_rate.value(),
+        ]
+    }
+
+    /// Driver with this configuration, all registers are marked dirty.
+    pub const fn build(&self) -> Tdc1000 {
+        Tdc1000 {
+            config0: self.config0,
+            config1: self.config1,
+            config2: self.config2,
+            config3: self.config3,
+            config4: self.config4,
+            amplifier_and_time_of_flight: self.amplifier_and_time_of_flight,
+            timeout: self.timeout,
+            clock_rate: self.clock_rate,
+            dirty: ALL_REGISTERS_DIRTY,
+            profiles: None,
+            tracer: NoTrace,
+        }
+    }
+}
+
+impl From<Tdc1000Config> for Tdc1000 {
+    fn from(config: Tdc1000Config) -> Self {
+        config.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::config::Tdc1000Config;
+    use crate::{
+        ChannelSwap, EchoQualificationThreshold, PgaGain, Tdc1000,
+        TimeOfFlightValue, TofTimeoutControl, TxPulses,
+    };
+
+    const PROFILE: Tdc1000Config = Tdc1000Config::new()
+        .pulses(TxPulses::new_const(17))
+        .channel_swap(ChannelSwap::EnableSwap)
+        .echo_qualification_threshold(EchoQualificationThreshold::Mv410)
+        .pga_gain(PgaGain::DB15)
+        .time_of_flight(TimeOfFlightValue::new_const(0x2a5))
+        .tof_timeout_ctrl(TofTimeoutControl::T0Times1024);
+    const PROFILE_REGISTERS: [u8; 9] = PROFILE.register_image();
+
+    #[test]
+    fn new_matches_driver_defaults() {
+        assert_eq!(Tdc1000Config::new().build(), Tdc1000::default());
+    }
+
+    #[test]
+    fn register_image_matches_driver() {
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_number_of_tx_pulses(TxPulses::new_const(17));
+        tdc1000.set_channel_swap(ChannelSwap::EnableSwap);
+        tdc1000.set_echo_qualification_threshold(
+            EchoQualificationThreshold::Mv410,
+        );
+        tdc1000.set_pga_gain(PgaGain::DB15);
+        tdc1000.set_time_of_flight(TimeOfFlightValue::new_const(0x2a5));
+        tdc1000.set_tof_timeout_ctrl(TofTimeoutControl::T0Times1024);
+
+        assert_eq!(PROFILE.build(), tdc1000);
+        assert_eq!(
+            PROFILE_REGISTERS,
+            [
+                tdc1000.get_config_0_value(),
+                tdc1000.get_config_1_value(),
+                tdc1000.get_config_2_value(),
+                tdc1000.get_config_3_value(),
+                tdc1000.get_config_4_value(),
+                tdc1000.get_tof_1_value(),
+                tdc1000.get_tof_0_value(),
+                tdc1000.get_timeout_value(),
+                tdc1000.get_clock_rate_value(),
+            ]
+        );
+    }
+}
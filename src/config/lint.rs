@@ -0,0 +1,145 @@
+//! Plausibility checks for a configuration.
+//!
+//! The device accepts any register value, but some combinations can not work
+//! with the given clock and transducer. [`lint`] reports them as a set of
+//! [`LintIssue`]s.
+
+use crate::config::Tdc1000Config;
+use crate::{
+    ChannelSwap, MeasurementMode, ReceiveEventsCnt, ReceiveMode,
+    TOFMeasurementMode,
+};
+
+/// Largest supported CLKIN frequency in Hz.
+const MAX_CLKIN: f32 = 16e6;
+
+/// Largest relative deviation of the TX frequency from the transducer
+/// frequency.
+const MAX_TX_FREQUENCY_DEVIATION: f32 = 0.1;
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LintIssue {
+    ClockTooFast,
+    /// CLKIN divided by the TX frequency divider is off the transducer
+    /// frequency.
+    TxFrequencyMismatch,
+    NoTxPulses,
+    /// Channel swap is only effective in measurement mode 2.
+    ChannelSwapWithoutMode2,
+    MultiEchoWithoutStopEvents,
+}
+
+const LINT_ISSUES: [LintIssue; 5] = [
+    LintIssue::ClockTooFast,
+    LintIssue::TxFrequencyMismatch,
+    LintIssue::NoTxPulses,
+    LintIssue::ChannelSwapWithoutMode2,
+    LintIssue::MultiEchoWithoutStopEvents,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LintReport {
+    issues: u8,
+}
+
+impl LintReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues == 0
+    }
+
+    pub fn contains(&self, issue: LintIssue) -> bool {
+        self.issues & 1 << issue as u8 != 0
+    }
+
+    pub fn issues(&self) -> impl Iterator<Item = LintIssue> + '_ {
+        LINT_ISSUES
+            .iter()
+            .copied()
+            .filter(move |issue| self.contains(*issue))
+    }
+
+    fn add(&mut self, issue: LintIssue) {
+        self.issues |= 1 << issue as u8;
+    }
+}
+
+/// Checks `config` for a CLKIN of `clkin` Hz and a transducer resonating at
+/// `transducer_frequency` Hz, `None` for temperature measurements.
+pub fn lint(
+    config: &Tdc1000Config,
+    clkin: f32,
+    transducer_frequency: Option<f32>,
+) -> LintReport {
+    let mut report = LintReport::default();
+    if clkin > MAX_CLKIN {
+        report.add(LintIssue::ClockTooFast);
+    }
+    if config.config2.measurement_mode == MeasurementMode::Temperature {
+        return report;
+    }
+
+    if let Some(transducer_frequency) = transducer_frequency {
+        let divider = 2 << config.config0.tx_frequency_divider as u32;
+        let tx_frequency = clkin / divider as f32;
+        if libm::fabsf(tx_frequency - transducer_frequency)
+            > MAX_TX_FREQUENCY_DEVIATION * transducer_frequency
+        {
+            report.add(LintIssue::TxFrequencyMismatch);
+        }
+    }
+    if config.config0.tx_pulses.get_value() == 0 {
+        report.add(LintIssue::NoTxPulses);
+    }
+    if config.config2.channel_swap == ChannelSwap::EnableSwap
+        && config.config2.tof_meas_mode != TOFMeasurementMode::Mode2
+    {
+        report.add(LintIssue::ChannelSwapWithoutMode2);
+    }
+    if config.config4.receive_mode == ReceiveMode::MultiEcho
+        && config.config1.receive_events_cnt
+            == ReceiveEventsCnt::DoNotCountStopEvents
+    {
+        report.add(LintIssue::MultiEchoWithoutStopEvents);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::config::lint::{lint, LintIssue};
+    use crate::config::Tdc1000Config;
+    use crate::{
+        ChannelSwap, ReceiveMode, TOFMeasurementMode, TxFrequencyDivider,
+        TxPulses,
+    };
+
+    #[test]
+    fn default_configuration_is_clean() {
+        let report = lint(&Tdc1000Config::new(), 8e6, Some(1e6));
+        assert!(report.is_clean());
+        assert_eq!(report.issues().count(), 0);
+    }
+
+    #[test]
+    fn issues_are_reported() {
+        let config = Tdc1000Config::new()
+            .tx_divider(TxFrequencyDivider::DivideBy16)
+            .pulses(TxPulses::new_const(0))
+            .channel_swap(ChannelSwap::EnableSwap)
+            .tof_meas_mode(TOFMeasurementMode::Mode1)
+            .receive_mode(ReceiveMode::MultiEcho);
+        let report = lint(&config, 20e6, Some(1e6));
+        assert!(report.contains(LintIssue::ClockTooFast));
+        assert!(report.contains(LintIssue::TxFrequencyMismatch));
+        assert!(report.contains(LintIssue::NoTxPulses));
+        assert!(report.contains(LintIssue::ChannelSwapWithoutMode2));
+        assert!(report.contains(LintIssue::MultiEchoWithoutStopEvents));
+        assert_eq!(report.issues().count(), 5);
+    }
+}
@@ -0,0 +1,177 @@
+//! Starting points for typical applications.
+//!
+//! Each [`Preset`] states the CLKIN and transducer frequency it was made for.
+//! Gain and echo threshold depend on the installation and should be tuned,
+//! e.g. with [`crate::sweep::SetupWizard`].
+
+use crate::config::lint::{lint, LintReport};
+use crate::config::Tdc1000Config;
+use crate::{
+    ChannelSwap, ClockInDiv, DampingMode, EchoQualificationThreshold,
+    EchoTimeout, ForceShortTimeOfFlight, MeasurementCycles, MeasurementMode,
+    PgaGain, ReceiveEventsCnt, ReceiveMode, ShortTofBlankPeriod,
+    TOFMeasurementMode, Tdc1000, TempClockDivider, TempMode, TempRtdSelect,
+    TimeOfFlightValue, TofTimeoutControl, TxFrequencyDivider, TxPulses,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Preset {
+    pub config: Tdc1000Config,
+    /// Assumed CLKIN frequency in Hz.
+    pub clkin: f32,
+    /// Assumed transducer resonance frequency in Hz, `None` for temperature
+    /// measurements.
+    pub transducer_frequency: Option<f32>,
+}
+
+impl Preset {
+    pub const fn build(&self) -> Tdc1000 {
+        self.config.build()
+    }
+
+    pub fn lint(&self) -> LintReport {
+        lint(&self.config, self.clkin, self.transducer_frequency)
+    }
+
+    /// Transit time water flow metering with a pair of 1 MHz transducers
+    /// and 8 MHz CLKIN. Mode 2 measures both directions alternately by
+    /// swapping the channels after every measurement.
+    pub const fn water_flow() -> Self {
+        Preset {
+            config: Tdc1000Config::new()
+                .tx_divider(TxFrequencyDivider::DivideBy8)
+                .pulses(TxPulses::new_const(10))
+                .receive_events(ReceiveEventsCnt::StopEvents1)
+                .tof_meas_mode(TOFMeasurementMode::Mode2)
+                .channel_swap(ChannelSwap::EnableSwap)
+                .echo_qualification_threshold(EchoQualificationThreshold::Mv125)
+                .pga_gain(PgaGain::DB9)
+                .tof_timeout_ctrl(TofTimeoutControl::T0Times1024),
+            clkin: 8e6,
+            transducer_frequency: Some(1e6),
+        }
+    }
+
+    /// Pulse-echo level sensing with a single 1 MHz transducer and 8 MHz
+    /// CLKIN. Damping and a short blanking period allow echoes from close
+    /// surfaces.
+    pub const fn level_sensing() -> Self {
+        Preset {
+            config: Tdc1000Config::new()
+                .tx_divider(TxFrequencyDivider::DivideBy8)
+                .pulses(TxPulses::new_const(5))
+                .receive_events(ReceiveEventsCnt::StopEvents1)
+                .tof_meas_mode(TOFMeasurementMode::Mode1)
+                .damping(DampingMode::EnableDamping)
+                .force_short_tof(ForceShortTimeOfFlight::ForceShortTimeOfFlight)
+                .short_tof_blank_period(ShortTofBlankPeriod::T0Times32)
+                .echo_qualification_threshold(EchoQualificationThreshold::Mv220)
+                .pga_gain(PgaGain::DB12)
+                .tof_timeout_ctrl(TofTimeoutControl::T0Times1024),
+            clkin: 8e6,
+            transducer_frequency: Some(1e6),
+        }
+    }
+
+    /// Fluid identification by the speed of sound over a fixed distance to a
+    /// reflector, 2 MHz transducer and 8 MHz CLKIN. Multi echo mode reports
+    /// the first three reflections.
+    pub const fn fluid_identification() -> Self {
+        Preset {
+            config: Tdc1000Config::new()
+                .tx_divider(TxFrequencyDivider::DivideBy4)
+                .pulses(TxPulses::new_const(5))
+                .receive_events(ReceiveEventsCnt::StopEvents3)
+                .receive_mode(ReceiveMode::MultiEcho)
+                .tof_meas_mode(TOFMeasurementMode::Mode1)
+                .damping(DampingMode::EnableDamping)
+                .echo_qualification_threshold(EchoQualificationThreshold::Mv125)
+                .pga_gain(PgaGain::DB6)
+                .tof_timeout_ctrl(TofTimeoutControl::T0Times1024),
+            clkin: 8e6,
+            transducer_frequency: Some(2e6),
+        }
+    }
+
+    /// Temperature measurement with a PT1000 pair and reference resistor,
+    /// 8 MHz CLKIN.
+    pub const fn rtd_pt1000() -> Self {
+        Preset {
+            config: Tdc1000Config::new()
+                .measure_mode(MeasurementMode::Temperature)
+                .temp_measurement_mode(TempMode::MeasureRefRtd1Rtd2)
+                .temp_rtd_type(TempRtdSelect::PT1000)
+                .temp_clock_divider(TempClockDivider::DivideBy8)
+                .measurement_cycles(MeasurementCycles::MeasurementCycles1),
+            clkin: 8e6,
+            transducer_frequency: None,
+        }
+    }
+
+    /// Like [`Preset::rtd_pt1000`] for PT500 sensors.
+    pub const fn rtd_pt500() -> Self {
+        let mut preset = Self::rtd_pt1000();
+        preset.config = preset.config.temp_rtd_type(TempRtdSelect::PT500);
+        preset
+    }
+
+    /// Ranging in air with a 40 kHz transducer and 10.24 MHz CLKIN. The
+    /// echo timeout is disabled as the TOF is far longer than the timeout
+    /// window, the maximum TIMING_REG value blanks the long ringing.
+    pub const fn air_ranging() -> Self {
+        Preset {
+            config: Tdc1000Config::new()
+                .tx_divider(TxFrequencyDivider::DivideBy256)
+                .pulses(TxPulses::new_const(8))
+                .receive_events(ReceiveEventsCnt::StopEvents1)
+                .tof_meas_mode(TOFMeasurementMode::Mode1)
+                .damping(DampingMode::EnableDamping)
+                .echo_qualification_threshold(EchoQualificationThreshold::Mv75)
+                .pga_gain(PgaGain::DB21)
+                .time_of_flight(TimeOfFlightValue::new_const(
+                    TimeOfFlightValue::HIGH,
+                ))
+                .echo_timeout(EchoTimeout::DisableTimeout)
+                .clock_in_div(ClockInDiv::DivideBy2),
+            clkin: 10.24e6,
+            transducer_frequency: Some(40e3),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::config::presets::Preset;
+    use crate::{MeasurementMode, TempRtdSelect};
+
+    #[test]
+    fn presets_pass_the_lint() {
+        for preset in [
+            Preset::water_flow(),
+            Preset::level_sensing(),
+            Preset::fluid_identification(),
+            Preset::rtd_pt1000(),
+            Preset::rtd_pt500(),
+            Preset::air_ranging(),
+        ]
+        .iter()
+        {
+            assert!(preset.lint().is_clean(), "{:?}", preset.lint());
+        }
+    }
+
+    #[test]
+    fn rtd_presets_differ_in_sensor_type() {
+        let pt1000 = Preset::rtd_pt1000().config;
+        let pt500 = Preset::rtd_pt500().config;
+        assert_eq!(
+            pt1000.config2.measurement_mode,
+            MeasurementMode::Temperature
+        );
+        assert_eq!(pt1000.config3.temp_rtd, TempRtdSelect::PT1000);
+        assert_eq!(pt500.config3.temp_rtd, TempRtdSelect::PT500);
+    }
+}
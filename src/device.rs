@@ -0,0 +1,467 @@
+//! Driver owning the bus and the control pins.
+//!
+//! [`Tdc1000`] only holds the configuration and borrows SPI and chip select
+//! for every access. [`Device`] owns them together with the optional CHSEL
+//! and ERRB pins, so channel selection works the same whether the channel is
+//! chosen by CONFIG_2 or by the CHSEL pin, and error conditions can be
+//! detected without polling ERROR_FLAGS.
+
+use crate::measurement::{
+    error_reset, MeasureError, TofCapture, TofMeasurement,
+};
+use crate::trace::{NoTrace, SpiTrace};
+use crate::{
+    ChannelSelect, Error, ErrorFlagsRead, ExternalChannelSelect, Pin, Tdc1000,
+};
+use core::convert::Infallible;
+use hal::{
+    blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::{InputPin, OutputPin},
+};
+
+/// Interval of polling ERRB while waiting for an error.
+const ERROR_POLL_INTERVAL_US: u32 = 10;
+
+/// Placeholder for an unconnected control pin.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoPin;
+
+impl OutputPin for NoPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Reads as high, i.e. no error signalled.
+impl InputPin for NoPin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(true)
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(false)
+    }
+}
+
+#[derive(Debug)]
+pub struct Device<SPI, CS, CHSEL = NoPin, ERRB = NoPin, T = NoTrace> {
+    spi: SPI,
+    cs: CS,
+    channel_select: Option<CHSEL>,
+    error_pin: Option<ERRB>,
+    /// Clear the error flags and ERRB when handling an error.
+    auto_clear_errors: bool,
+    /// Error flags read by [`Device::handle_error_interrupt`] and not yet
+    /// attached to a measurement.
+    pending_error: Option<ErrorFlagsRead>,
+    tdc1000: Tdc1000<T>,
+}
+
+impl<SPI, CS, T: SpiTrace> Device<SPI, CS, NoPin, NoPin, T> {
+    pub fn new(spi: SPI, cs: CS, tdc1000: Tdc1000<T>) -> Self {
+        Device {
+            spi,
+            cs,
+            channel_select: None,
+            error_pin: None,
+            auto_clear_errors: true,
+            pending_error: None,
+            tdc1000,
+        }
+    }
+}
+
+impl<SPI, CS, ERRB, T: SpiTrace> Device<SPI, CS, NoPin, ERRB, T> {
+    /// Uses `chsel` to select the channel while
+    /// [`ExternalChannelSelect::EnableExternalChannelSelect`] is configured.
+    /// CHSEL low selects channel 1, high channel 2.
+    pub fn with_channel_select_pin<CHSEL: OutputPin>(
+        self,
+        chsel: CHSEL,
+    ) -> Device<SPI, CS, CHSEL, ERRB, T> {
+        Device {
+            spi: self.spi,
+            cs: self.cs,
+            channel_select: Some(chsel),
+            error_pin: self.error_pin,
+            auto_clear_errors: self.auto_clear_errors,
+            pending_error: self.pending_error,
+            tdc1000: self.tdc1000,
+        }
+    }
+}
+
+impl<SPI, CS, CHSEL, T: SpiTrace> Device<SPI, CS, CHSEL, NoPin, T> {
+    /// Uses the open drain ERRB output, low while an error flag is set.
+    pub fn with_error_pin<ERRB: InputPin>(
+        self,
+        errb: ERRB,
+    ) -> Device<SPI, CS, CHSEL, ERRB, T> {
+        Device {
+            spi: self.spi,
+            cs: self.cs,
+            channel_select: self.channel_select,
+            error_pin: Some(errb),
+            auto_clear_errors: self.auto_clear_errors,
+            pending_error: self.pending_error,
+            tdc1000: self.tdc1000,
+        }
+    }
+}
+
+impl<SPI, CS, CHSEL, ERRB, T: SpiTrace> Device<SPI, CS, CHSEL, ERRB, T> {
+    pub fn tdc1000(&self) -> &Tdc1000<T> {
+        &self.tdc1000
+    }
+
+    /// Changes take effect with the next [`Device::write_changes`].
+    pub fn tdc1000_mut(&mut self) -> &mut Tdc1000<T> {
+        &mut self.tdc1000
+    }
+
+    pub fn release(self) -> (SPI, CS, Option<CHSEL>, Option<ERRB>, Tdc1000<T>) {
+        (
+            self.spi,
+            self.cs,
+            self.channel_select,
+            self.error_pin,
+            self.tdc1000,
+        )
+    }
+
+    /// Whether handling an error clears the error flags and releases ERRB,
+    /// enabled by default. Without clearing, ERRB stays low and the next
+    /// error can not be told apart.
+    pub fn set_auto_clear_errors(&mut self, auto_clear: bool) {
+        self.auto_clear_errors = auto_clear;
+    }
+
+    pub fn write_changes<CsE, SpiE>(&mut self) -> Result<(), Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        self.tdc1000.write_changes(&mut self.cs, &mut self.spi)
+    }
+
+    /// Selects `channel` by the CHSEL pin if the external channel select is
+    /// enabled and by CONFIG_2 otherwise. Channel profiles are applied in
+    /// both cases and changed registers are written before the pin changes.
+    ///
+    /// Fails with [`Error::InvalidConfiguration`] if the external channel
+    /// select is enabled without a CHSEL pin, or if channel profiles are set
+    /// while channel swap is enabled.
+    pub fn select_channel<CsE, SpiE, PinE>(
+        &mut self,
+        channel: ChannelSelect,
+    ) -> Result<(), Error<CsE, SpiE, PinE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        CHSEL: OutputPin<Error = PinE>,
+    {
+        if self.tdc1000.profiles_conflict() {
+            return Err(Error::InvalidConfiguration);
+        }
+        if self.tdc1000.config2.ext_channel_select
+            == ExternalChannelSelect::DisableExternalChannelSelect
+        {
+            self.tdc1000.set_active_channel(channel);
+            return self.write_changes().map_err(Error::widen);
+        }
+
+        let chsel = self
+            .channel_select
+            .as_mut()
+            .ok_or(Error::InvalidConfiguration)?;
+        // CONFIG_2 CH_SEL is ignored by the device, only the shadow follows
+        // the pin.
+        self.tdc1000.config2.channel_select = channel;
+        self.tdc1000.apply_profile_settings(channel);
+        self.tdc1000
+            .write_changes(&mut self.cs, &mut self.spi)
+            .map_err(Error::widen)?;
+        match channel {
+            ChannelSelect::Channel1 => chsel.set_low(),
+            ChannelSelect::Channel2 => chsel.set_high(),
+        }
+        .map_err(|error| Error::PinError(Pin::ChannelSelect, error))
+    }
+
+    /// Whether ERRB signals an error. Always `false` without an ERRB pin.
+    pub fn error_pending<PinE>(&self) -> Result<bool, PinE>
+    where
+        ERRB: InputPin<Error = PinE>,
+    {
+        match &self.error_pin {
+            Some(errb) => errb.is_low(),
+            None => Ok(false),
+        }
+    }
+
+    /// Polls ERRB for up to `timeout_us` and returns whether an error was
+    /// signalled.
+    pub fn wait_for_error<D, PinE>(
+        &self,
+        delay: &mut D,
+        timeout_us: u32,
+    ) -> Result<bool, PinE>
+    where
+        ERRB: InputPin<Error = PinE>,
+        D: DelayUs<u32>,
+    {
+        let mut waited = 0;
+        loop {
+            if self.error_pending()? {
+                return Ok(true);
+            }
+            if waited >= timeout_us {
+                return Ok(false);
+            }
+            delay.delay_us(ERROR_POLL_INTERVAL_US);
+            waited = waited.saturating_add(ERROR_POLL_INTERVAL_US);
+        }
+    }
+
+    /// Reads and decodes ERROR_FLAGS, clears them if auto clear is enabled
+    /// and keeps them for the next [`Device::measure`].
+    ///
+    /// Meant to be called from the ERRB falling edge interrupt: it neither
+    /// blocks nor touches the pins, the device only has to be shared with
+    /// the interrupt, e.g. in a critical section mutex.
+    pub fn handle_error_interrupt<CsE, SpiE>(
+        &mut self,
+    ) -> Result<ErrorFlagsRead, Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        self.handle_error(false)
+    }
+
+    /// Like [`Tdc1000::check_errors`], but keeps the flags pending and only
+    /// clears them with auto clear enabled.
+    fn handle_error<CsE, SpiE>(
+        &mut self,
+        timed_out: bool,
+    ) -> Result<ErrorFlagsRead, Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        let error_flags =
+            self.tdc1000.read_error(&mut self.cs, &mut self.spi)?;
+        if error_flags.has_error() {
+            self.pending_error = Some(error_flags);
+        }
+        if let (true, Some(reset)) =
+            (self.auto_clear_errors, error_reset(timed_out, &error_flags))
+        {
+            self.tdc1000
+                .reset_error(&mut self.cs, &mut self.spi, reset)?;
+        }
+        Ok(error_flags)
+    }
+
+    /// Error flags handled but not yet attached to a measurement.
+    pub fn take_pending_error(&mut self) -> Option<ErrorFlagsRead> {
+        self.pending_error.take()
+    }
+
+    /// Captures one measurement and attaches the error flags signalled
+    /// since the last measurement, see [`Tdc1000::measure`]. With an ERRB pin
+    /// ERROR_FLAGS is only read if ERRB is low or the measurement timed out.
+    pub fn measure<C, CsE, SpiE, PinE>(
+        &mut self,
+        capture: &mut C,
+    ) -> Result<TofMeasurement, MeasureError<CsE, SpiE, C::Error, PinE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        ERRB: InputPin<Error = PinE>,
+        C: TofCapture,
+    {
+        let mut measurement =
+            capture.capture().map_err(MeasureError::CaptureError)?;
+        let timed_out = measurement.is_timeout();
+        let error_pending = self
+            .error_pending()
+            .map_err(|error| Error::PinError(Pin::Error, error))?;
+        if self.error_pin.is_none() || timed_out || error_pending {
+            self.handle_error(timed_out).map_err(Error::widen)?;
+        }
+        if let Some(error_flags) = self.pending_error.take() {
+            measurement.record_error(error_flags);
+        }
+        Ok(measurement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::device::Device;
+    use crate::measurement::{TofCapture, TofMeasurement};
+    use crate::profile::ChannelProfile;
+    use crate::simulator::Simulator;
+    use crate::{
+        ChannelSelect, ErrNoSignalRead, Error, ExternalChannelSelect, PgaGain,
+        Tdc1000,
+    };
+    use core::convert::Infallible;
+    use hal::blocking::delay::DelayUs;
+    use hal::digital::v2::InputPin;
+
+    struct NoDelay;
+
+    impl DelayUs<u32> for NoDelay {
+        fn delay_us(&mut self, _us: u32) {}
+    }
+
+    struct FixedCapture;
+
+    impl TofCapture for FixedCapture {
+        type Error = Infallible;
+
+        fn capture(&mut self) -> Result<TofMeasurement, Infallible> {
+            Ok(TofMeasurement::new(&[10e-6]))
+        }
+    }
+
+    #[test]
+    fn channel_is_selected_by_register() {
+        let simulator = Simulator::new();
+        let mut device =
+            Device::new(simulator.spi(), simulator.cs(), Tdc1000::default());
+        device.select_channel(ChannelSelect::Channel2).unwrap();
+        assert_eq!(simulator.active_channel(), ChannelSelect::Channel2);
+        device.select_channel(ChannelSelect::Channel1).unwrap();
+        assert_eq!(simulator.active_channel(), ChannelSelect::Channel1);
+    }
+
+    #[test]
+    fn channel_is_selected_by_pin() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_external_channel_select(
+            ExternalChannelSelect::EnableExternalChannelSelect,
+        );
+        let mut device = Device::new(simulator.spi(), simulator.cs(), tdc1000)
+            .with_channel_select_pin(simulator.channel_select_pin());
+        device.write_changes().unwrap();
+        let config2 = simulator.register(2);
+
+        device.select_channel(ChannelSelect::Channel2).unwrap();
+        assert_eq!(simulator.active_channel(), ChannelSelect::Channel2);
+        assert_eq!(simulator.register(2), config2);
+        device.select_channel(ChannelSelect::Channel1).unwrap();
+        assert_eq!(simulator.active_channel(), ChannelSelect::Channel1);
+    }
+
+    #[test]
+    fn profiles_follow_the_pin() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_external_channel_select(
+            ExternalChannelSelect::EnableExternalChannelSelect,
+        );
+        let channel2 = ChannelProfile {
+            pga_gain: PgaGain::DB21,
+            ..ChannelProfile::default()
+        };
+        tdc1000.set_channel_profiles(ChannelProfile::default(), channel2);
+        let mut device = Device::new(simulator.spi(), simulator.cs(), tdc1000)
+            .with_channel_select_pin(simulator.channel_select_pin());
+        device.select_channel(ChannelSelect::Channel2).unwrap();
+        assert_eq!(simulator.active_channel(), ChannelSelect::Channel2);
+        assert_eq!(simulator.register(5), device.tdc1000().get_tof_1_value());
+        assert_eq!(
+            device.tdc1000().current_channel_profile().pga_gain,
+            PgaGain::DB21
+        );
+    }
+
+    #[test]
+    fn external_select_requires_a_pin() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_external_channel_select(
+            ExternalChannelSelect::EnableExternalChannelSelect,
+        );
+        let mut device = Device::new(simulator.spi(), simulator.cs(), tdc1000);
+        assert_eq!(
+            device.select_channel(ChannelSelect::Channel2),
+            Err(Error::InvalidConfiguration)
+        );
+    }
+
+    #[test]
+    fn errb_errors_are_attached_to_the_measurement() {
+        let simulator = Simulator::new();
+        let mut device =
+            Device::new(simulator.spi(), simulator.cs(), Tdc1000::default())
+                .with_error_pin(simulator.error_pin());
+        let measurement = device.measure(&mut FixedCapture).unwrap();
+        assert_eq!(measurement.error_flags(), None);
+        assert!(!device.wait_for_error(&mut NoDelay, 100).unwrap());
+
+        simulator.set_register(7, 0b010);
+        assert!(device.wait_for_error(&mut NoDelay, 100).unwrap());
+        let measurement = device.measure(&mut FixedCapture).unwrap();
+        let error_flags = measurement.error_flags().unwrap();
+        assert_eq!(*error_flags.no_signal(), ErrNoSignalRead::NoSignalTimeout);
+        assert!(!device.error_pending().unwrap());
+        assert_eq!(measurement.first_stop(), Some(10e-6));
+    }
+
+    #[test]
+    fn longest_error_wait_ends() {
+        /// ERRB that never signals an error.
+        struct HighPin;
+
+        impl InputPin for HighPin {
+            type Error = Infallible;
+
+            fn is_high(&self) -> Result<bool, Infallible> {
+                Ok(true)
+            }
+
+            fn is_low(&self) -> Result<bool, Infallible> {
+                Ok(false)
+            }
+        }
+
+        let simulator = Simulator::new();
+        let device =
+            Device::new(simulator.spi(), simulator.cs(), Tdc1000::default())
+                .with_error_pin(HighPin);
+        assert_eq!(device.wait_for_error(&mut NoDelay, u32::MAX), Ok(false));
+    }
+
+    #[test]
+    fn interrupt_handler_keeps_flags_without_auto_clear() {
+        let simulator = Simulator::new();
+        let mut device =
+            Device::new(simulator.spi(), simulator.cs(), Tdc1000::default())
+                .with_error_pin(simulator.error_pin());
+        device.set_auto_clear_errors(false);
+        simulator.set_register(7, 0b100);
+        let error_flags = device.handle_error_interrupt().unwrap();
+        assert!(error_flags.has_error());
+        assert!(device.error_pending().unwrap());
+        assert_eq!(device.take_pending_error(), Some(error_flags));
+        assert_eq!(device.take_pending_error(), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Transducer and installation diagnostics.
+//!
+//! Combines the error flag statistics and TOF jitter of a series of
+//! measurements with an optional echo amplitude estimate and the speed of
+//! sound derived from the mean TOF into a single [`DiagnosticStatus`].
+//!
+//! The checks are heuristics evaluated in this order:
+//! * almost no echo at all and no amplitude found by the threshold sweep:
+//!   [`DiagnosticStatus::OpenTransducer`]
+//! * almost no echo although the sweep found one, or a speed of sound outside
+//!   the plausible range of the medium: [`DiagnosticStatus::EmptyPipe`]
+//! * echoes above the largest threshold: [`DiagnosticStatus::Saturation`]
+//! * low echo amplitude or many missing echoes, typical for scaling:
+//!   [`DiagnosticStatus::WeakSignal`]
+//! * high TOF jitter or occasional missing echoes:
+//!   [`DiagnosticStatus::AirBubbles`]
+
+use crate::amplitude::AmplitudeEstimate;
+use crate::measurement::MeasurementStatistics;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiagnosticStatus {
+    Ok,
+    NoData,
+    OpenTransducer,
+    EmptyPipe,
+    Saturation,
+    WeakSignal,
+    AirBubbles,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiagnosticThresholds {
+    /// Share of measurements without echo above which the transducer is
+    /// considered disconnected or the pipe empty.
+    pub no_signal_ratio: f32,
+    /// Share of measurements with `SignalHigh` indicating saturation.
+    pub saturation_ratio: f32,
+    /// Share of failed measurements indicating a weak signal.
+    pub weak_signal_ratio: f32,
+    /// Share of failed measurements indicating air bubbles.
+    pub bubble_ratio: f32,
+    /// Echo amplitude at the receiver input in mV below which the signal is
+    /// considered weak.
+    pub min_amplitude: f32,
+    /// Largest acceptable TOF standard deviation in seconds.
+    pub max_jitter: f32,
+    /// Plausible speed of sound range of the medium in m/s.
+    pub min_speed_of_sound: f32,
+    pub max_speed_of_sound: f32,
+}
+
+impl Default for DiagnosticThresholds {
+    /// Thresholds for water.
+    fn default() -> Self {
+        DiagnosticThresholds {
+            no_signal_ratio: 0.95,
+            saturation_ratio: 0.2,
+            weak_signal_ratio: 0.2,
+            bubble_ratio: 0.02,
+            min_amplitude: 1.0,
+            max_jitter: 1e-8,
+            min_speed_of_sound: 1400.0,
+            max_speed_of_sound: 1600.0,
+        }
+    }
+}
+
+pub struct Diagnostics {
+    thresholds: DiagnosticThresholds,
+    path_length: Option<f32>,
+}
+
+impl Diagnostics {
+    pub fn new(thresholds: DiagnosticThresholds) -> Self {
+        Diagnostics {
+            thresholds,
+            path_length: None,
+        }
+    }
+
+    /// Acoustic path length between TX and RX in metres, enables the speed
+    /// of sound plausibility check.
+    pub fn set_path_length(&mut self, path_length: f32) {
+        self.path_length = Some(path_length);
+    }
+
+    pub fn diagnose(
+        &self,
+        statistics: &MeasurementStatistics,
+        amplitude: Option<&AmplitudeEstimate>,
+    ) -> DiagnosticStatus {
+        let thresholds = &self.thresholds;
+        let measurements = statistics.errors.measurements;
+        if measurements == 0 {
+            return DiagnosticStatus::NoData;
+        }
+        let ratio = |count: u16| count as f32 / measurements as f32;
+
+        if ratio(statistics.errors.no_signal) >= thresholds.no_signal_ratio {
+            return match amplitude {
+                Some(_) => DiagnosticStatus::EmptyPipe,
+                None => DiagnosticStatus::OpenTransducer,
+            };
+        }
+        if let Some(speed_of_sound) = self.speed_of_sound(statistics) {
+            if speed_of_sound < thresholds.min_speed_of_sound
+                || speed_of_sound > thresholds.max_speed_of_sound
+            {
+                return DiagnosticStatus::EmptyPipe;
+            }
+        }
+        if ratio(statistics.errors.signal_high) >= thresholds.saturation_ratio {
+            return DiagnosticStatus::Saturation;
+        }
+
+        let failure_ratio =
+            ratio(measurements.saturating_sub(statistics.successes));
+        let weak_amplitude = matches!(
+            amplitude,
+            Some(amplitude) if amplitude.millivolts() < thresholds.min_amplitude
+        );
+        if weak_amplitude || failure_ratio >= thresholds.weak_signal_ratio {
+            return DiagnosticStatus::WeakSignal;
+        }
+        let high_jitter = matches!(
+            statistics.tof.jitter(),
+            Some(jitter) if jitter > thresholds.max_jitter
+        );
+        if high_jitter || failure_ratio >= thresholds.bubble_ratio {
+            return DiagnosticStatus::AirBubbles;
+        }
+        DiagnosticStatus::Ok
+    }
+
+    /// Speed of sound in m/s from the mean TOF of the successful
+    /// measurements.
+    pub fn speed_of_sound(
+        &self,
+        statistics: &MeasurementStatistics,
+    ) -> Option<f32> {
+        match (self.path_length, statistics.tof.mean()) {
+            (Some(path_length), Some(tof)) if tof > 0.0 => {
+                Some(path_length / tof)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::amplitude::AmplitudeEstimate;
+    use crate::diagnostics::{
+        DiagnosticStatus, DiagnosticThresholds, Diagnostics,
+    };
+    use crate::measurement::MeasurementStatistics;
+    use crate::{ErrNoSignalRead, ErrSignalHighRead, ErrorFlagsRead};
+
+    const PATH_LENGTH: f32 = 0.1;
+
+    fn statistics(
+        measurements: u16,
+        no_signal: u16,
+        signal_high: u16,
+        tofs: &[f32],
+    ) -> MeasurementStatistics {
+        let mut statistics = MeasurementStatistics::default();
+        for index in 0..measurements {
+            let error_flags = ErrorFlagsRead {
+                no_signal: if index < no_signal {
+                    ErrNoSignalRead::NoSignalTimeout
+                } else {
+                    ErrNoSignalRead::NoError
+                },
+                signal_high: if index >= measurements - signal_high {
+                    ErrSignalHighRead::SignalHigh
+                } else {
+                    ErrSignalHighRead::NoError
+                },
+                ..ErrorFlagsRead::default()
+            };
+            statistics.errors.record(&error_flags);
+        }
+        for tof in tofs {
+            statistics.successes += 1;
+            statistics.tof.add(*tof);
+        }
+        statistics
+    }
+
+    fn diagnostics() -> Diagnostics {
+        let mut diagnostics = Diagnostics::new(DiagnosticThresholds::default());
+        diagnostics.set_path_length(PATH_LENGTH);
+        diagnostics
+    }
+
+    #[test]
+    fn healthy_installation_is_ok() {
+        let tof = PATH_LENGTH / 1480.0;
+        let statistics = statistics(4, 0, 0, &[tof, tof, tof, tof]);
+        assert_eq!(
+            diagnostics().diagnose(&statistics, None),
+            DiagnosticStatus::Ok
+        );
+    }
+
+    #[test]
+    fn missing_echo_separates_open_transducer_from_empty_pipe() {
+        let statistics = statistics(20, 20, 0, &[]);
+        assert_eq!(
+            diagnostics().diagnose(&statistics, None),
+            DiagnosticStatus::OpenTransducer
+        );
+        let amplitude = AmplitudeEstimate {
+            lower: 0.2,
+            upper: Some(0.3),
+        };
+        assert_eq!(
+            diagnostics().diagnose(&statistics, Some(&amplitude)),
+            DiagnosticStatus::EmptyPipe
+        );
+    }
+
+    #[test]
+    fn implausible_speed_of_sound_indicates_empty_pipe() {
+        let tof = PATH_LENGTH / 343.0;
+        let statistics = statistics(2, 0, 0, &[tof, tof]);
+        assert_eq!(
+            diagnostics().diagnose(&statistics, None),
+            DiagnosticStatus::EmptyPipe
+        );
+    }
+
+    #[test]
+    fn signal_problems_are_classified() {
+        let tof = PATH_LENGTH / 1480.0;
+        let saturated = statistics(4, 0, 2, &[tof, tof]);
+        assert_eq!(
+            diagnostics().diagnose(&saturated, None),
+            DiagnosticStatus::Saturation
+        );
+
+        let weak = statistics(10, 3, 0, &[tof; 7]);
+        assert_eq!(
+            diagnostics().diagnose(&weak, None),
+            DiagnosticStatus::WeakSignal
+        );
+
+        let bubbles = statistics(10, 1, 0, &[tof; 9]);
+        assert_eq!(
+            diagnostics().diagnose(&bubbles, None),
+            DiagnosticStatus::AirBubbles
+        );
+
+        let small = AmplitudeEstimate {
+            lower: 0.2,
+            upper: Some(0.3),
+        };
+        let statistics = statistics(2, 0, 0, &[tof, tof]);
+        assert_eq!(
+            diagnostics().diagnose(&statistics, Some(&small)),
+            DiagnosticStatus::WeakSignal
+        );
+    }
+}
@@ -2,20 +2,160 @@
 
 extern crate embedded_hal as hal;
 
+pub mod agc;
+pub mod amplitude;
+pub mod anemometer;
+pub mod config;
+pub mod device;
+pub mod diagnostics;
+pub mod measurement;
+pub mod nonblocking;
+pub mod persist;
+pub mod power;
+pub mod probe;
+pub mod profile;
+pub mod scheduler;
+pub mod scrub;
+pub mod simulator;
+pub mod supervisor;
+pub mod sweep;
+#[cfg(test)]
+mod test_support;
+pub mod trace;
+
+use core::convert::{Infallible, TryFrom};
+use core::fmt;
 use hal::{
     blocking::spi::{Transfer, Write},
     digital::v2::OutputPin,
 };
+use profile::ChannelProfile;
+use trace::{Direction, NoTrace, SpiTrace};
 
 const SPI_WRITE_BIT: u8 = 0x40;
 
-#[derive(Debug)]
-pub enum Error<CsE, SpiE> {
+/// A value outside the legal range `low..=high`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OutOfRange<V> {
+    pub value: V,
+    pub low: V,
+    pub high: V,
+}
+
+impl<V: fmt::Display> fmt::Display for OutOfRange<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is outside of {}..={}",
+            self.value, self.low, self.high
+        )
+    }
+}
+
+/// Control pins besides the chip select.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pin {
+    Enable,
+    Reset,
+    ChannelSelect,
+    /// The ERRB output.
+    Error,
+}
+
+/// Driver error. `PinE` is the error type of the control pins and only used
+/// by operations driving one of them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<CsE, SpiE, PinE = Infallible> {
     ChipSelectError(CsE),
     SpiError(SpiE),
+    PinError(Pin, PinE),
+    /// No STOP event arrived within the expected time.
+    MeasurementTimeout,
+    /// The requested settings can not be represented by the device.
+    InvalidConfiguration,
+    /// `register` read back `actual` after `expected` was written.
+    VerifyMismatch {
+        register: u8,
+        expected: u8,
+        actual: u8,
+    },
+}
+
+impl<CsE, SpiE> Error<CsE, SpiE> {
+    /// Converts the error of an operation without control pins into the
+    /// error type of one driving pins with error type `PinE`.
+    pub fn widen<PinE>(self) -> Error<CsE, SpiE, PinE> {
+        match self {
+            Error::ChipSelectError(error) => Error::ChipSelectError(error),
+            Error::SpiError(error) => Error::SpiError(error),
+            Error::PinError(_, error) => match error {},
+            Error::MeasurementTimeout => Error::MeasurementTimeout,
+            Error::InvalidConfiguration => Error::InvalidConfiguration,
+            Error::VerifyMismatch {
+                register,
+                expected,
+                actual,
+            } => Error::VerifyMismatch {
+                register,
+                expected,
+                actual,
+            },
+        }
+    }
+}
+
+impl<CsE, SpiE, PinE> fmt::Display for Error<CsE, SpiE, PinE>
+where
+    CsE: fmt::Debug,
+    SpiE: fmt::Debug,
+    PinE: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ChipSelectError(error) => {
+                write!(f, "chip select error: {:?}", error)
+            }
+            Error::SpiError(error) => write!(f, "SPI error: {:?}", error),
+            Error::PinError(pin, error) => {
+                write!(f, "{:?} pin error: {:?}", pin, error)
+            }
+            Error::MeasurementTimeout => f.write_str("measurement timeout"),
+            Error::InvalidConfiguration => f.write_str("invalid configuration"),
+            Error::VerifyMismatch {
+                register,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "register {:#04x} reads {:#04x} instead of {:#04x}",
+                register, actual, expected
+            ),
+        }
+    }
+}
+
+/// Reports the kind of the underlying SPI error, all other errors are
+/// [`embedded_hal_1::spi::ErrorKind::Other`].
+#[cfg(feature = "embedded-hal-1")]
+impl<CsE, SpiE, PinE> embedded_hal_1::spi::Error for Error<CsE, SpiE, PinE>
+where
+    CsE: fmt::Debug,
+    SpiE: embedded_hal_1::spi::Error,
+    PinE: fmt::Debug,
+{
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        match self {
+            Error::SpiError(error) => error.kind(),
+            _ => embedded_hal_1::spi::ErrorKind::Other,
+        }
+    }
 }
 
 #[repr(u8)]
+#[derive(Copy, Clone)]
 enum ConfigAddresses {
     Config0,
     Config1,
@@ -29,12 +169,40 @@ enum ConfigAddresses {
     ClockRate,
 }
 
+/// Register values after power up or a RESET pulse, indexed by address.
+pub const RESET_VALUES: [u8; 10] =
+    [0x45, 0x40, 0x00, 0x03, 0x1f, 0x00, 0x00, 0x00, 0x19, 0x00];
+
+/// Writable configuration registers in address order.
+const CONFIG_REGISTERS: [ConfigAddresses; 9] = [
+    ConfigAddresses::Config0,
+    ConfigAddresses::Config1,
+    ConfigAddresses::Config2,
+    ConfigAddresses::Config3,
+    ConfigAddresses::Config4,
+    ConfigAddresses::Tof1,
+    ConfigAddresses::Tof0,
+    ConfigAddresses::TimeOut,
+    ConfigAddresses::ClockRate,
+];
+
+/// Dirty mask with the bits of all [`CONFIG_REGISTERS`] set.
+const ALL_REGISTERS_DIRTY: u16 = 0b11_0111_1111;
+
+#[cfg(feature = "serde")]
+fn all_registers_dirty() -> u16 {
+    ALL_REGISTERS_DIRTY
+}
+
 const FREQUENCY_DIVIDER_BIT_OFFSET: u8 = 5;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TxFrequencyDivider {
     DivideBy2,
     DivideBy4,
+    #[default]
     DivideBy8,
     DivideBy16,
     DivideBy32,
@@ -42,35 +210,66 @@ pub enum TxFrequencyDivider {
     DivideBy128,
     DivideBy256,
 }
-impl Default for TxFrequencyDivider {
-    fn default() -> Self {
-        TxFrequencyDivider::DivideBy8
-    }
-}
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8"))]
 pub struct TxPulses(u8);
 impl TxPulses {
     pub const LOW: u8 = 0;
     pub const HIGH: u8 = 31;
-    pub fn new(pulses: u8) -> Self {
+    /// Fails with the legal range if `pulses` is out of range.
+    pub fn try_new(pulses: u8) -> Result<Self, OutOfRange<u8>> {
+        if pulses > Self::HIGH {
+            return Err(OutOfRange {
+                value: pulses,
+                low: Self::LOW,
+                high: Self::HIGH,
+            });
+        }
+        Ok(TxPulses(pulses))
+    }
+    /// For constants, an out of range value fails at compile time when used
+    /// in a `const` context and panics otherwise.
+    pub const fn new_const(pulses: u8) -> Self {
+        assert!(pulses <= Self::HIGH, "TX pulses out of range");
+        TxPulses(pulses)
+    }
+    /// Clamps `pulses` to the legal range.
+    pub fn new_clamped(pulses: u8) -> Self {
         TxPulses(trim_value_u8(pulses, Self::HIGH, Self::LOW))
     }
-    pub fn get_value(&self) -> u8 {
+    #[deprecated(note = "silently clamps, use `try_new` or `new_clamped`")]
+    pub fn new(pulses: u8) -> Self {
+        Self::new_clamped(pulses)
+    }
+    pub const fn get_value(&self) -> u8 {
         self.0
     }
 }
 
+impl TryFrom<u8> for TxPulses {
+    type Error = OutOfRange<u8>;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::try_new(value)
+    }
+}
+
 impl Default for TxPulses {
     fn default() -> Self {
-        TxPulses::new(5)
+        TxPulses::new_const(5)
     }
 }
 
 const MEASUREMENT_CYCLES_BIT_OFFSET: u8 = 3;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MeasurementCycles {
+    #[default]
     MeasurementCycles1,
     MeasurementCycles2,
     MeasurementCycles4,
@@ -80,15 +279,13 @@ pub enum MeasurementCycles {
     MeasurementCycles64,
     MeasurementCycles128,
 }
-impl Default for MeasurementCycles {
-    fn default() -> Self {
-        MeasurementCycles::MeasurementCycles1
-    }
-}
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReceiveEventsCnt {
+    #[default]
     DoNotCountStopEvents,
     StopEvents1,
     StopEvents2,
@@ -99,224 +296,255 @@ pub enum ReceiveEventsCnt {
     StopEvents7,
 }
 
-impl Default for ReceiveEventsCnt {
-    fn default() -> Self {
-        ReceiveEventsCnt::DoNotCountStopEvents
-    }
-}
-
 const VOLTAGE_REFERENCE_BIT_OFFSET: u8 = 7;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VoltageReference {
+    #[default]
     Internal,
     External,
 }
-impl Default for VoltageReference {
-    fn default() -> Self {
-        VoltageReference::Internal
-    }
-}
 
 const MEASUREMENT_MODE_BIT_OFFSET: u8 = 6;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MeasurementMode {
+    #[default]
     TimeOfFlight,
     Temperature,
 }
-impl Default for MeasurementMode {
-    fn default() -> Self {
-        MeasurementMode::TimeOfFlight
-    }
-}
 
 const DAMPING_MODE_BIT_OFFSET: u8 = 5;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DampingMode {
+    #[default]
     DisableDamping,
     EnableDamping,
 }
-impl Default for DampingMode {
-    fn default() -> Self {
-        DampingMode::DisableDamping
-    }
-}
 
 const CHANNEL_SWAP_BIT_OFFSET: u8 = 4;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChannelSwap {
+    #[default]
     DisableSwap,
     EnableSwap,
 }
-impl Default for ChannelSwap {
-    fn default() -> Self {
-        ChannelSwap::DisableSwap
-    }
-}
 
 const EXTERNAL_CHANNEL_SELECT_BIT_OFFSET: u8 = 3;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExternalChannelSelect {
+    #[default]
     DisableExternalChannelSelect,
     EnableExternalChannelSelect,
 }
-impl Default for ExternalChannelSelect {
-    fn default() -> Self {
-        ExternalChannelSelect::DisableExternalChannelSelect
-    }
-}
 
 const CHANNEL_SELECT_BIT_OFFSET: u8 = 2;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChannelSelect {
+    #[default]
     Channel1,
     Channel2,
 }
-impl Default for ChannelSelect {
-    fn default() -> Self {
-        ChannelSelect::Channel1
-    }
-}
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TOFMeasurementMode {
+    #[default]
     Mode0,
     Mode1,
     Mode2,
 }
-impl Default for TOFMeasurementMode {
-    fn default() -> Self {
-        TOFMeasurementMode::Mode0
-    }
-}
 
 const TEMP_MODE_BIT_OFFSET: u8 = 6;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TempMode {
+    #[default]
     MeasureRefRtd1Rtd2,
     MeasureRefRtd1,
 }
-impl Default for TempMode {
-    fn default() -> Self {
-        TempMode::MeasureRefRtd1Rtd2
-    }
-}
 
 const TEMP_RTD_SELECT_BIT_OFFSET: u8 = 5;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TempRtdSelect {
+    #[default]
     PT1000,
     PT500,
 }
-impl Default for TempRtdSelect {
-    fn default() -> Self {
-        TempRtdSelect::PT1000
-    }
-}
 
 const TEMP_CLK_DIV_BIT_OFFSET: u8 = 4;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TempClockDivider {
+    #[default]
     DivideBy8,
     UseTxFreqDivider,
 }
-impl Default for TempClockDivider {
-    fn default() -> Self {
-        TempClockDivider::DivideBy8
-    }
-}
 
 const BLANKING_BIT_OFFSET: u8 = 3;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PowerBlanking {
+    #[default]
     DisablePowerBlanking,
     EnablePowerBlanking,
 }
-impl Default for PowerBlanking {
-    fn default() -> Self {
-        PowerBlanking::DisablePowerBlanking
-    }
-}
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EchoQualificationThreshold {
     Mv35,
     Mv50,
     Mv75,
+    #[default]
     Mv125,
     Mv220,
     Mv410,
     Mv775,
     Mv1500,
 }
-impl Default for EchoQualificationThreshold {
-    fn default() -> Self {
-        EchoQualificationThreshold::Mv125
+impl EchoQualificationThreshold {
+    pub fn millivolts(&self) -> u16 {
+        match self {
+            EchoQualificationThreshold::Mv35 => 35,
+            EchoQualificationThreshold::Mv50 => 50,
+            EchoQualificationThreshold::Mv75 => 75,
+            EchoQualificationThreshold::Mv125 => 125,
+            EchoQualificationThreshold::Mv220 => 220,
+            EchoQualificationThreshold::Mv410 => 410,
+            EchoQualificationThreshold::Mv775 => 775,
+            EchoQualificationThreshold::Mv1500 => 1500,
+        }
     }
 }
 
+pub(crate) const ECHO_THRESHOLDS: [EchoQualificationThreshold; 8] = [
+    EchoQualificationThreshold::Mv35,
+    EchoQualificationThreshold::Mv50,
+    EchoQualificationThreshold::Mv75,
+    EchoQualificationThreshold::Mv125,
+    EchoQualificationThreshold::Mv220,
+    EchoQualificationThreshold::Mv410,
+    EchoQualificationThreshold::Mv775,
+    EchoQualificationThreshold::Mv1500,
+];
+
 const RECEIVE_MODE_BIT_OFFSET: u8 = 6;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReceiveMode {
+    #[default]
     SingleEcho,
     MultiEcho,
 }
-impl Default for ReceiveMode {
-    fn default() -> Self {
-        ReceiveMode::SingleEcho
-    }
-}
 
 const TRIGGER_EDGE_POLARITY_BIT_OFFSET: u8 = 5;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TriggerEdgePolarity {
+    #[default]
     RisingEdge,
     FallingEdge,
 }
-impl Default for TriggerEdgePolarity {
-    fn default() -> Self {
-        TriggerEdgePolarity::RisingEdge
-    }
-}
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8"))]
 pub struct TxPulseShiftPosition(u8);
 impl TxPulseShiftPosition {
     pub const LOW: u8 = 0;
     pub const HIGH: u8 = 31;
-    pub fn new(pulse_shift_position: u8) -> Self {
+    /// Fails with the legal range if `pulse_shift_position` is out of range.
+    pub fn try_new(pulse_shift_position: u8) -> Result<Self, OutOfRange<u8>> {
+        if pulse_shift_position > Self::HIGH {
+            return Err(OutOfRange {
+                value: pulse_shift_position,
+                low: Self::LOW,
+                high: Self::HIGH,
+            });
+        }
+        Ok(TxPulseShiftPosition(pulse_shift_position))
+    }
+    /// For constants, an out of range value fails at compile time when used
+    /// in a `const` context and panics otherwise.
+    pub const fn new_const(pulse_shift_position: u8) -> Self {
+        assert!(
+            pulse_shift_position <= Self::HIGH,
+            "TX pulse shift position out of range"
+        );
+        TxPulseShiftPosition(pulse_shift_position)
+    }
+    /// Clamps `pulse_shift_position` to the legal range.
+    pub fn new_clamped(pulse_shift_position: u8) -> Self {
         TxPulseShiftPosition(trim_value_u8(
             pulse_shift_position,
             Self::HIGH,
             Self::LOW,
         ))
     }
-    pub fn get_value(&self) -> u8 {
+    #[deprecated(note = "silently clamps, use `try_new` or `new_clamped`")]
+    pub fn new(pulse_shift_position: u8) -> Self {
+        Self::new_clamped(pulse_shift_position)
+    }
+    pub const fn get_value(&self) -> u8 {
         self.0
     }
 }
+impl TryFrom<u8> for TxPulseShiftPosition {
+    type Error = OutOfRange<u8>;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::try_new(value)
+    }
+}
+
 impl Default for TxPulseShiftPosition {
     fn default() -> Self {
-        TxPulseShiftPosition::new(31)
+        TxPulseShiftPosition::new_const(31)
     }
 }
 
 const PGA_GAIN_BIT_OFFSET: u8 = 5;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PgaGain {
+    #[default]
     DB0,
     DB3,
     DB6,
@@ -326,200 +554,248 @@ pub enum PgaGain {
     DB18,
     DB21,
 }
-impl Default for PgaGain {
-    fn default() -> Self {
-        PgaGain::DB0
+impl PgaGain {
+    pub fn decibels(&self) -> u8 {
+        *self as u8 * 3
     }
 }
 
+pub(crate) const PGA_GAINS: [PgaGain; 8] = [
+    PgaGain::DB0,
+    PgaGain::DB3,
+    PgaGain::DB6,
+    PgaGain::DB9,
+    PgaGain::DB12,
+    PgaGain::DB15,
+    PgaGain::DB18,
+    PgaGain::DB21,
+];
+
 const PGA_CTRL_BIT_OFFSET: u8 = 4;
 const LNA_CTRL_BIT_OFFSET: u8 = 3;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AmplifierControl {
+    #[default]
     Active,
     BypassedAndPoweredOff,
 }
-impl Default for AmplifierControl {
-    fn default() -> Self {
-        AmplifierControl::Active
-    }
-}
 
 const LNA_FB_BIT_OFFSET: u8 = 2;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LnaFeedbackMode {
+    #[default]
     CapacitiveMode,
     ResistiveMode,
 }
-impl Default for LnaFeedbackMode {
-    fn default() -> Self {
-        LnaFeedbackMode::CapacitiveMode
-    }
-}
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u16"))]
 pub struct TimeOfFlightValue(u16);
 impl TimeOfFlightValue {
     pub const LOW: u16 = 0;
     pub const HIGH: u16 = 1023;
-    pub fn new(time_of_flight_value: u16) -> Self {
+    /// Fails with the legal range if `time_of_flight_value` is out of range.
+    pub fn try_new(time_of_flight_value: u16) -> Result<Self, OutOfRange<u16>> {
+        if time_of_flight_value > Self::HIGH {
+            return Err(OutOfRange {
+                value: time_of_flight_value,
+                low: Self::LOW,
+                high: Self::HIGH,
+            });
+        }
+        Ok(TimeOfFlightValue(time_of_flight_value))
+    }
+    /// For constants, an out of range value fails at compile time when used
+    /// in a `const` context and panics otherwise.
+    pub const fn new_const(time_of_flight_value: u16) -> Self {
+        assert!(
+            time_of_flight_value <= Self::HIGH,
+            "time of flight value out of range"
+        );
+        TimeOfFlightValue(time_of_flight_value)
+    }
+    /// Clamps `time_of_flight_value` to the legal range.
+    pub fn new_clamped(time_of_flight_value: u16) -> Self {
         TimeOfFlightValue(trim_value_u16(
             time_of_flight_value,
             Self::HIGH,
             Self::LOW,
         ))
     }
-    pub fn get_2_high_bits_of_tof(&self) -> u8 {
+    #[deprecated(note = "silently clamps, use `try_new` or `new_clamped`")]
+    pub fn new(time_of_flight_value: u16) -> Self {
+        Self::new_clamped(time_of_flight_value)
+    }
+    pub const fn get_2_high_bits_of_tof(&self) -> u8 {
         (self.0 >> 8) as u8
     }
-    pub fn get_8_low_bits_of_tof(&self) -> u8 {
+    pub const fn get_8_low_bits_of_tof(&self) -> u8 {
         self.0 as u8
     }
 }
+impl TryFrom<u16> for TimeOfFlightValue {
+    type Error = OutOfRange<u16>;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        Self::try_new(value)
+    }
+}
+
 impl Default for TimeOfFlightValue {
     fn default() -> Self {
-        TimeOfFlightValue::new(0)
+        TimeOfFlightValue::new_const(0)
     }
 }
 
 const ERR_SIG_WEAK_BIT_OFFSET: u8 = 2;
 #[repr(u8)]
-#[derive(PartialEq)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ErrSignalWeakRead {
+    #[default]
     NoError,
     SignalWeekTimeout,
 }
-impl Default for ErrSignalWeakRead {
-    fn default() -> Self {
-        ErrSignalWeakRead::NoError
-    }
-}
 
 const ERR_NO_SIG_BIT_OFFSET: u8 = 1;
 #[repr(u8)]
-#[derive(PartialEq)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ErrNoSignalRead {
+    #[default]
     NoError,
     NoSignalTimeout,
 }
-impl Default for ErrNoSignalRead {
-    fn default() -> Self {
-        ErrNoSignalRead::NoError
-    }
-}
 
 #[repr(u8)]
-#[derive(PartialEq)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ErrSignalHighRead {
+    #[default]
     NoError,
     SignalHigh,
 }
-impl Default for ErrSignalHighRead {
-    fn default() -> Self {
-        ErrSignalHighRead::NoError
-    }
-}
 
 const FORCE_SHORT_TOF_BIT_OFFSET: u8 = 6;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ForceShortTimeOfFlight {
+    #[default]
     Disabled,
     ForceShortTimeOfFlight,
 }
-impl Default for ForceShortTimeOfFlight {
-    fn default() -> Self {
-        ForceShortTimeOfFlight::Disabled
-    }
-}
 
 const SHORT_TOF_BLANK_PERIOD_BIT_OFFSET: u8 = 3;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ShortTofBlankPeriod {
     T0Times8,
     T0Times16,
     T0Times32,
+    #[default]
     T0Times64,
     T0Times128,
     T0Times256,
     T0Times512,
     T0Times1024,
 }
-impl Default for ShortTofBlankPeriod {
-    fn default() -> Self {
-        ShortTofBlankPeriod::T0Times64
-    }
-}
 
 const ECHO_TIMEOUT_BIT_OFFSET: u8 = 2;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EchoTimeout {
+    #[default]
     EnableTimeout,
     DisableTimeout,
 }
-impl Default for EchoTimeout {
-    fn default() -> Self {
-        EchoTimeout::EnableTimeout
-    }
-}
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TofTimeoutControl {
     T0Times128,
+    #[default]
     T0Times256,
     T0Times512,
     T0Times1024,
 }
-impl Default for TofTimeoutControl {
-    fn default() -> Self {
-        TofTimeoutControl::T0Times256
-    }
-}
 
 const CLOCK_IN_DIV_BIT_OFFSET: u8 = 2;
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ClockInDiv {
+    #[default]
     DivideBy1,
     DivideBy2,
 }
-impl Default for ClockInDiv {
-    fn default() -> Self {
-        ClockInDiv::DivideBy1
-    }
-}
 
 #[repr(u8)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AutoZeroPeriod {
+    #[default]
     T0Times64,
     T0Times128,
     T0Times256,
     T0Times512,
 }
-impl Default for AutoZeroPeriod {
-    fn default() -> Self {
-        AutoZeroPeriod::T0Times64
-    }
-}
 
-#[derive(Default)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config0 {
     tx_frequency_divider: TxFrequencyDivider,
     tx_pulses: TxPulses,
 }
 
-#[derive(Default)]
+impl Config0 {
+    const fn value(&self) -> u8 {
+        let tx_frequency_divider = self.tx_frequency_divider as u8;
+        let tx_pulses = self.tx_pulses.get_value();
+        tx_frequency_divider << FREQUENCY_DIVIDER_BIT_OFFSET | tx_pulses
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config1 {
     measurement_cycles: MeasurementCycles,
     receive_events_cnt: ReceiveEventsCnt,
 }
 
-#[derive(Default)]
+impl Config1 {
+    const fn value(&self) -> u8 {
+        let measurement_cycles = self.measurement_cycles as u8;
+        let stop_pulse_count = self.receive_events_cnt as u8;
+        measurement_cycles << MEASUREMENT_CYCLES_BIT_OFFSET | stop_pulse_count
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config2 {
     voltage_reference: VoltageReference,
     measurement_mode: MeasurementMode,
@@ -530,7 +806,28 @@ pub struct Config2 {
     tof_meas_mode: TOFMeasurementMode,
 }
 
-#[derive(Default)]
+impl Config2 {
+    const fn value(&self) -> u8 {
+        let voltage_reference = self.voltage_reference as u8;
+        let measurement_mode = self.measurement_mode as u8;
+        let damping = self.damping_mode as u8;
+        let channel_swap = self.channel_swap as u8;
+        let ext_channel_select = self.ext_channel_select as u8;
+        let channel_select = self.channel_select as u8;
+        let tof_measurement_mode = self.tof_meas_mode as u8;
+        voltage_reference << VOLTAGE_REFERENCE_BIT_OFFSET
+            | measurement_mode << MEASUREMENT_MODE_BIT_OFFSET
+            | damping << DAMPING_MODE_BIT_OFFSET
+            | channel_swap << CHANNEL_SWAP_BIT_OFFSET
+            | ext_channel_select << EXTERNAL_CHANNEL_SELECT_BIT_OFFSET
+            | channel_select << CHANNEL_SELECT_BIT_OFFSET
+            | tof_measurement_mode
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config3 {
     temp_mode: TempMode,
     temp_rtd: TempRtdSelect,
@@ -539,14 +836,44 @@ pub struct Config3 {
     echo_qualification_threshold: EchoQualificationThreshold,
 }
 
-#[derive(Default)]
+impl Config3 {
+    const fn value(&self) -> u8 {
+        let temp_mode = self.temp_mode as u8;
+        let temp_rtd_sel = self.temp_rtd as u8;
+        let temp_clk_div = self.temp_clk_div as u8;
+        let blanking = self.blanking as u8;
+        let echo_th = self.echo_qualification_threshold as u8;
+        temp_mode << TEMP_MODE_BIT_OFFSET
+            | temp_rtd_sel << TEMP_RTD_SELECT_BIT_OFFSET
+            | temp_clk_div << TEMP_CLK_DIV_BIT_OFFSET
+            | blanking << BLANKING_BIT_OFFSET
+            | echo_th
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config4 {
     receive_mode: ReceiveMode,
     trigger_edge_polarity: TriggerEdgePolarity,
     tx_pulse_shift_position: TxPulseShiftPosition,
 }
 
-#[derive(Default)]
+impl Config4 {
+    const fn value(&self) -> u8 {
+        let receive_mode = self.receive_mode as u8;
+        let trigger_edge_polarity = self.trigger_edge_polarity as u8;
+        let pulse_shift_position = self.tx_pulse_shift_position.get_value();
+        receive_mode << RECEIVE_MODE_BIT_OFFSET
+            | trigger_edge_polarity << TRIGGER_EDGE_POLARITY_BIT_OFFSET
+            | pulse_shift_position
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AmplifierAndTimeOfFlight {
     pga_gain: PgaGain,
     pga_ctrl: AmplifierControl,
@@ -555,7 +882,24 @@ pub struct AmplifierAndTimeOfFlight {
     time_of_flight: TimeOfFlightValue,
 }
 
-#[derive(Default)]
+impl AmplifierAndTimeOfFlight {
+    const fn value(&self) -> u8 {
+        let pga_gain = self.pga_gain as u8;
+        let pga_ctrl = self.pga_ctrl as u8;
+        let lna_ctrl = self.lna_ctrl as u8;
+        let lna_fb = self.lna_fb as u8;
+        let timing_reg_high = self.time_of_flight.get_2_high_bits_of_tof();
+        pga_gain << PGA_GAIN_BIT_OFFSET
+            | pga_ctrl << PGA_CTRL_BIT_OFFSET
+            | lna_ctrl << LNA_CTRL_BIT_OFFSET
+            | lna_fb << LNA_FB_BIT_OFFSET
+            | timing_reg_high
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ErrorFlagsRead {
     signal_week: ErrSignalWeakRead,
     no_signal: ErrNoSignalRead,
@@ -572,28 +916,44 @@ impl ErrorFlagsRead {
     pub fn signal_high(&self) -> &ErrSignalHighRead {
         &self.signal_high
     }
+    pub fn has_error(&self) -> bool {
+        self.signal_week == ErrSignalWeakRead::SignalWeekTimeout
+            || self.no_signal == ErrNoSignalRead::NoSignalTimeout
+            || self.signal_high == ErrSignalHighRead::SignalHigh
+    }
 }
 
 #[repr(u8)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ErrorFlagsWrite {
+    #[default]
     DoNothing,
     ResetAllErrorFlagsAndErrorPin,
     ResetStateMachineAndMeasurement,
     ResetErrorStatemachineAndMeasurement,
 }
-impl Default for ErrorFlagsWrite {
-    fn default() -> Self {
-        ErrorFlagsWrite::DoNothing
-    }
-}
 
-#[derive(Default)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClockRate {
     clock_in_div: ClockInDiv,
     auto_zero_period: AutoZeroPeriod,
 }
 
-#[derive(Default)]
+impl ClockRate {
+    const fn value(&self) -> u8 {
+        let clock_in_div = self.clock_in_div as u8;
+        let auto_zero_period = self.auto_zero_period as u8;
+        clock_in_div << CLOCK_IN_DIV_BIT_OFFSET | auto_zero_period
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimeOut {
     force_short_tof: ForceShortTimeOfFlight,
     short_tof_blank_period: ShortTofBlankPeriod,
@@ -601,8 +961,23 @@ pub struct TimeOut {
     tof_timeout_crl: TofTimeoutControl,
 }
 
-#[derive(Default)]
-pub struct Tdc1000 {
+impl TimeOut {
+    const fn value(&self) -> u8 {
+        let force_short_tof = self.force_short_tof as u8;
+        let short_tof_blank_period = self.short_tof_blank_period as u8;
+        let echo_timeout = self.echo_timeout as u8;
+        let tof_timeout_ctrl = self.tof_timeout_crl as u8;
+        force_short_tof << FORCE_SHORT_TOF_BIT_OFFSET
+            | short_tof_blank_period << SHORT_TOF_BLANK_PERIOD_BIT_OFFSET
+            | echo_timeout << ECHO_TIMEOUT_BIT_OFFSET
+            | tof_timeout_ctrl
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tdc1000<T = NoTrace> {
     config0: Config0,
     config1: Config1,
     config2: Config2,
@@ -611,23 +986,118 @@ pub struct Tdc1000 {
     amplifier_and_time_of_flight: AmplifierAndTimeOfFlight,
     timeout: TimeOut,
     clock_rate: ClockRate,
+    /// Registers changed since their last successful write, one bit per
+    /// register address.
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip, default = "all_registers_dirty")
+    )]
+    dirty: u16,
+    profiles: Option<[ChannelProfile; 2]>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    tracer: T,
+}
+
+/// Compares the configuration and the tracer, not which registers still
+/// have to be written.
+impl<T: PartialEq> PartialEq for Tdc1000<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.config0 == other.config0
+            && self.config1 == other.config1
+            && self.config2 == other.config2
+            && self.config3 == other.config3
+            && self.config4 == other.config4
+            && self.amplifier_and_time_of_flight
+                == other.amplifier_and_time_of_flight
+            && self.timeout == other.timeout
+            && self.clock_rate == other.clock_rate
+            && self.profiles == other.profiles
+            && self.tracer == other.tracer
+    }
+}
+
+impl<T: Eq> Eq for Tdc1000<T> {}
+
+impl Default for Tdc1000 {
+    fn default() -> Self {
+        Tdc1000 {
+            config0: Config0::default(),
+            config1: Config1::default(),
+            config2: Config2::default(),
+            config3: Config3::default(),
+            config4: Config4::default(),
+            amplifier_and_time_of_flight: AmplifierAndTimeOfFlight::default(),
+            timeout: TimeOut::default(),
+            clock_rate: ClockRate::default(),
+            dirty: ALL_REGISTERS_DIRTY,
+            profiles: None,
+            tracer: NoTrace,
+        }
+    }
 }
 
-impl Tdc1000 {
+impl<T: SpiTrace> Tdc1000<T> {
+    /// Reports every register access to `tracer`.
+    pub fn with_trace<U: SpiTrace>(self, tracer: U) -> Tdc1000<U> {
+        Tdc1000 {
+            config0: self.config0,
+            config1: self.config1,
+            config2: self.config2,
+            config3: self.config3,
+            config4: self.config4,
+            amplifier_and_time_of_flight: self.amplifier_and_time_of_flight,
+            timeout: self.timeout,
+            clock_rate: self.clock_rate,
+            dirty: self.dirty,
+            profiles: self.profiles,
+            tracer,
+        }
+    }
+
+    pub fn tracer(&self) -> &T {
+        &self.tracer
+    }
+
+    pub fn tracer_mut(&mut self) -> &mut T {
+        &mut self.tracer
+    }
+
+    /// Whether any register changed since it was last written.
+    pub fn has_changes(&self) -> bool {
+        self.dirty != 0
+    }
+
+    /// Marks all registers for rewriting by [`Tdc1000::write_changes`], e.g.
+    /// after the device was reset or power cycled.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = ALL_REGISTERS_DIRTY;
+    }
+
+    fn mark_dirty(&mut self, address: ConfigAddresses) {
+        self.dirty |= 1 << address as u8;
+    }
+
     pub fn set_tx_frequency_divider(&mut self, divider: TxFrequencyDivider) {
         self.config0.tx_frequency_divider = divider;
+        self.mark_dirty(ConfigAddresses::Config0);
     }
 
     pub fn set_number_of_tx_pulses(&mut self, pulses: TxPulses) {
         self.config0.tx_pulses = pulses;
+        self.mark_dirty(ConfigAddresses::Config0);
+        if let Some(profile) = self.active_profile_mut() {
+            profile.tx_pulses = pulses;
+        }
     }
 
     pub fn set_measurement_cycles(&mut self, cycles: MeasurementCycles) {
         self.config1.measurement_cycles = cycles;
+        self.mark_dirty(ConfigAddresses::Config1);
     }
 
     pub fn set_receive_events(&mut self, events_cnt: ReceiveEventsCnt) {
         self.config1.receive_events_cnt = events_cnt;
+        self.mark_dirty(ConfigAddresses::Config1);
     }
 
     pub fn set_common_voltage_reference_mode(
@@ -635,18 +1105,22 @@ impl Tdc1000 {
         voltage_reference: VoltageReference,
     ) {
         self.config2.voltage_reference = voltage_reference;
+        self.mark_dirty(ConfigAddresses::Config2);
     }
 
     pub fn set_measure_mode(&mut self, measure_mode: MeasurementMode) {
         self.config2.measurement_mode = measure_mode;
+        self.mark_dirty(ConfigAddresses::Config2);
     }
 
     pub fn set_damping(&mut self, damping_mode: DampingMode) {
         self.config2.damping_mode = damping_mode;
+        self.mark_dirty(ConfigAddresses::Config2);
     }
 
     pub fn set_channel_swap(&mut self, channel_swap: ChannelSwap) {
         self.config2.channel_swap = channel_swap;
+        self.mark_dirty(ConfigAddresses::Config2);
     }
 
     pub fn set_external_channel_select(
@@ -654,10 +1128,16 @@ impl Tdc1000 {
         external_channel_select: ExternalChannelSelect,
     ) {
         self.config2.ext_channel_select = external_channel_select;
+        self.mark_dirty(ConfigAddresses::Config2);
     }
 
+    /// Also applies the channel profile of `channel`, if profiles are set.
     pub fn set_active_channel(&mut self, channel: ChannelSelect) {
-        self.config2.channel_select = channel;
+        if self.config2.channel_select != channel {
+            self.config2.channel_select = channel;
+            self.mark_dirty(ConfigAddresses::Config2);
+        }
+        self.apply_channel_profile(channel);
     }
 
     pub fn set_tof_meas_mode(
@@ -665,6 +1145,7 @@ impl Tdc1000 {
         tof_measurement_mode: TOFMeasurementMode,
     ) {
         self.config2.tof_meas_mode = tof_measurement_mode;
+        self.mark_dirty(ConfigAddresses::Config2);
     }
 
     pub fn set_temp_measurement_mode(
@@ -672,18 +1153,22 @@ impl Tdc1000 {
         temp_measurement_mode: TempMode,
     ) {
         self.config3.temp_mode = temp_measurement_mode;
+        self.mark_dirty(ConfigAddresses::Config3);
     }
 
     pub fn set_temp_rtd_type(&mut self, temp_rtd: TempRtdSelect) {
         self.config3.temp_rtd = temp_rtd;
+        self.mark_dirty(ConfigAddresses::Config3);
     }
 
     pub fn set_temp_clock_divider(&mut self, temp_clock_div: TempClockDivider) {
         self.config3.temp_clk_div = temp_clock_div;
+        self.mark_dirty(ConfigAddresses::Config3);
     }
 
     pub fn set_blanking(&mut self, blanking: PowerBlanking) {
         self.config3.blanking = blanking;
+        self.mark_dirty(ConfigAddresses::Config3);
     }
 
     pub fn set_echo_qualification_threshold(
@@ -691,14 +1176,20 @@ impl Tdc1000 {
         threshold: EchoQualificationThreshold,
     ) {
         self.config3.echo_qualification_threshold = threshold;
+        self.mark_dirty(ConfigAddresses::Config3);
+        if let Some(profile) = self.active_profile_mut() {
+            profile.echo_qualification_threshold = threshold;
+        }
     }
 
     pub fn set_receive_mode(&mut self, receive_mode: ReceiveMode) {
         self.config4.receive_mode = receive_mode;
+        self.mark_dirty(ConfigAddresses::Config4);
     }
 
     pub fn set_trigger(&mut self, trigger: TriggerEdgePolarity) {
         self.config4.trigger_edge_polarity = trigger;
+        self.mark_dirty(ConfigAddresses::Config4);
     }
 
     pub fn set_tx_pulse_shift_position(
@@ -706,30 +1197,42 @@ impl Tdc1000 {
         position: TxPulseShiftPosition,
     ) {
         self.config4.tx_pulse_shift_position = position;
+        self.mark_dirty(ConfigAddresses::Config4);
     }
 
     pub fn set_time_of_flight(&mut self, tof: TimeOfFlightValue) {
         self.amplifier_and_time_of_flight.time_of_flight = tof;
+        self.mark_dirty(ConfigAddresses::Tof1);
+        self.mark_dirty(ConfigAddresses::Tof0);
     }
 
     pub fn set_pga_gain(&mut self, gain: PgaGain) {
         self.amplifier_and_time_of_flight.pga_gain = gain;
+        self.mark_dirty(ConfigAddresses::Tof1);
+        if let Some(profile) = self.active_profile_mut() {
+            profile.pga_gain = gain;
+        }
     }
 
     pub fn set_pga_control(&mut self, control: AmplifierControl) {
         self.amplifier_and_time_of_flight.pga_ctrl = control;
+        self.mark_dirty(ConfigAddresses::Tof1);
     }
 
     pub fn set_lna_control(&mut self, control: AmplifierControl) {
         self.amplifier_and_time_of_flight.lna_ctrl = control;
+        self.mark_dirty(ConfigAddresses::Tof1);
     }
 
     pub fn set_lna_feedback_mode(&mut self, feedback_mode: LnaFeedbackMode) {
         self.amplifier_and_time_of_flight.lna_fb = feedback_mode;
+        self.mark_dirty(ConfigAddresses::Tof1);
     }
 
     pub fn set_tof_value(&mut self, tof_value: TimeOfFlightValue) {
         self.amplifier_and_time_of_flight.time_of_flight = tof_value;
+        self.mark_dirty(ConfigAddresses::Tof1);
+        self.mark_dirty(ConfigAddresses::Tof0);
     }
 
     pub fn set_force_short_tof(
@@ -737,6 +1240,7 @@ impl Tdc1000 {
         force_short_tof: ForceShortTimeOfFlight,
     ) {
         self.timeout.force_short_tof = force_short_tof;
+        self.mark_dirty(ConfigAddresses::TimeOut);
     }
 
     pub fn set_short_tof_blank_period(
@@ -744,113 +1248,94 @@ impl Tdc1000 {
         blank_period: ShortTofBlankPeriod,
     ) {
         self.timeout.short_tof_blank_period = blank_period;
+        self.mark_dirty(ConfigAddresses::TimeOut);
     }
 
     pub fn set_echo_timeout(&mut self, timeout: EchoTimeout) {
         self.timeout.echo_timeout = timeout;
+        self.mark_dirty(ConfigAddresses::TimeOut);
     }
 
     pub fn set_tof_timeout_ctrl(&mut self, timeout_ctrl: TofTimeoutControl) {
         self.timeout.tof_timeout_crl = timeout_ctrl;
+        self.mark_dirty(ConfigAddresses::TimeOut);
     }
 
     pub fn set_clock_in_div(&mut self, clock_in_div: ClockInDiv) {
         self.clock_rate.clock_in_div = clock_in_div;
+        self.mark_dirty(ConfigAddresses::ClockRate);
     }
 
     pub fn set_auto_zero_period(&mut self, auto_zero_period: AutoZeroPeriod) {
         self.clock_rate.auto_zero_period = auto_zero_period;
+        self.mark_dirty(ConfigAddresses::ClockRate);
     }
 
-    pub fn get_config_0_value(&self) -> u8 {
-        let tx_frequency_divider = self.config0.tx_frequency_divider as u8;
-        let tx_pulses = self.config0.tx_pulses.get_value();
-        tx_frequency_divider << FREQUENCY_DIVIDER_BIT_OFFSET | tx_pulses
+    pub const fn get_config_0_value(&self) -> u8 {
+        self.config0.value()
     }
 
-    pub fn get_config_1_value(&self) -> u8 {
-        let measurement_cycles = self.config1.measurement_cycles as u8;
-        let stop_pulse_count = self.config1.receive_events_cnt as u8;
-        measurement_cycles << MEASUREMENT_CYCLES_BIT_OFFSET | stop_pulse_count
+    pub const fn get_config_1_value(&self) -> u8 {
+        self.config1.value()
     }
 
-    pub fn get_config_2_value(&self) -> u8 {
-        let voltage_reference = self.config2.voltage_reference as u8;
-        let measurement_mode = self.config2.measurement_mode as u8;
-        let damping = self.config2.damping_mode as u8;
-        let channel_swap = self.config2.channel_swap as u8;
-        let ext_channel_select = self.config2.ext_channel_select as u8;
-        let channel_select = self.config2.channel_select as u8;
-        let tof_measurement_mode = self.config2.tof_meas_mode as u8;
-        voltage_reference << VOLTAGE_REFERENCE_BIT_OFFSET
-            | measurement_mode << MEASUREMENT_MODE_BIT_OFFSET
-            | damping << DAMPING_MODE_BIT_OFFSET
-            | channel_swap << CHANNEL_SWAP_BIT_OFFSET
-            | ext_channel_select << EXTERNAL_CHANNEL_SELECT_BIT_OFFSET
-            | channel_select << CHANNEL_SELECT_BIT_OFFSET
-            | tof_measurement_mode
+    pub const fn get_config_2_value(&self) -> u8 {
+        self.config2.value()
     }
 
-    pub fn get_config_3_value(&self) -> u8 {
-        let temp_mode = self.config3.temp_mode as u8;
-        let temp_rtd_sel = self.config3.temp_rtd as u8;
-        let temp_clk_div = self.config3.temp_clk_div as u8;
-        let blanking = self.config3.blanking as u8;
-        let echo_th = self.config3.echo_qualification_threshold as u8;
-        temp_mode << TEMP_MODE_BIT_OFFSET
-            | temp_rtd_sel << TEMP_RTD_SELECT_BIT_OFFSET
-            | temp_clk_div << TEMP_CLK_DIV_BIT_OFFSET
-            | blanking << BLANKING_BIT_OFFSET
-            | echo_th
+    pub const fn get_config_3_value(&self) -> u8 {
+        self.config3.value()
     }
 
-    pub fn get_config_4_value(&self) -> u8 {
-        let receive_mode = self.config4.receive_mode as u8;
-        let trigger_edge_polarity = self.config4.trigger_edge_polarity as u8;
-        let pulse_shift_position =
-            self.config4.tx_pulse_shift_position.get_value();
-        receive_mode << RECEIVE_MODE_BIT_OFFSET
-            | trigger_edge_polarity << TRIGGER_EDGE_POLARITY_BIT_OFFSET
-            | pulse_shift_position
+    pub const fn get_config_4_value(&self) -> u8 {
+        self.config4.value()
     }
 
-    pub fn get_tof_1_value(&self) -> u8 {
-        let pga_gain = self.amplifier_and_time_of_flight.pga_gain as u8;
-        let pga_ctrl = self.amplifier_and_time_of_flight.pga_ctrl as u8;
-        let lna_ctrl = self.amplifier_and_time_of_flight.lna_ctrl as u8;
-        let lna_fb = self.amplifier_and_time_of_flight.lna_fb as u8;
-        let timing_reg_high = self
-            .amplifier_and_time_of_flight
-            .time_of_flight
-            .get_2_high_bits_of_tof();
-        pga_gain << PGA_GAIN_BIT_OFFSET
-            | pga_ctrl << PGA_CTRL_BIT_OFFSET
-            | lna_ctrl << LNA_CTRL_BIT_OFFSET
-            | lna_fb << LNA_FB_BIT_OFFSET
-            | timing_reg_high
+    pub const fn get_tof_1_value(&self) -> u8 {
+        self.amplifier_and_time_of_flight.value()
     }
 
-    pub fn get_tof_0_value(&self) -> u8 {
+    pub const fn get_tof_0_value(&self) -> u8 {
         self.amplifier_and_time_of_flight
             .time_of_flight
             .get_8_low_bits_of_tof()
     }
 
-    pub fn get_timeout_value(&self) -> u8 {
-        let force_short_tof = self.timeout.force_short_tof as u8;
-        let short_tof_blank_period = self.timeout.short_tof_blank_period as u8;
-        let echo_timeout = self.timeout.echo_timeout as u8;
-        let tof_timeout_ctrl = self.timeout.tof_timeout_crl as u8;
-        force_short_tof << FORCE_SHORT_TOF_BIT_OFFSET
-            | short_tof_blank_period << SHORT_TOF_BLANK_PERIOD_BIT_OFFSET
-            | echo_timeout << ECHO_TIMEOUT_BIT_OFFSET
-            | tof_timeout_ctrl
+    pub const fn get_timeout_value(&self) -> u8 {
+        self.timeout.value()
     }
 
-    pub fn get_clock_rate_value(&self) -> u8 {
-        let clock_in_div = self.clock_rate.clock_in_div as u8;
-        let auto_zero_period = self.clock_rate.auto_zero_period as u8;
-        clock_in_div << CLOCK_IN_DIV_BIT_OFFSET | auto_zero_period
+    pub const fn get_clock_rate_value(&self) -> u8 {
+        self.clock_rate.value()
+    }
+
+    fn get_register_value(&self, address: ConfigAddresses) -> u8 {
+        match address {
+            ConfigAddresses::Config0 => self.get_config_0_value(),
+            ConfigAddresses::Config1 => self.get_config_1_value(),
+            ConfigAddresses::Config2 => self.get_config_2_value(),
+            ConfigAddresses::Config3 => self.get_config_3_value(),
+            ConfigAddresses::Config4 => self.get_config_4_value(),
+            ConfigAddresses::Tof1 => self.get_tof_1_value(),
+            ConfigAddresses::Tof0 => self.get_tof_0_value(),
+            ConfigAddresses::ErrFlag => ErrorFlagsWrite::DoNothing as u8,
+            ConfigAddresses::TimeOut => self.get_timeout_value(),
+            ConfigAddresses::ClockRate => self.get_clock_rate_value(),
+        }
+    }
+
+    fn write_register<CS, SPI, CsE, SpiE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+        address: ConfigAddresses,
+    ) -> Result<(), Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        let value = self.get_register_value(address);
+        self.write_to_spi(cs, spi, [address as u8 | SPI_WRITE_BIT, value])
     }
 
     pub fn write_settings<CS, SPI, CsE, SpiE>(
@@ -938,6 +1423,25 @@ impl Tdc1000 {
         Ok(())
     }
 
+    /// Writes only the registers changed since their last successful write.
+    /// A new driver starts with all registers dirty.
+    pub fn write_changes<CS, SPI, CsE, SpiE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+    ) -> Result<(), Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        for address in CONFIG_REGISTERS.iter() {
+            if self.dirty & 1 << *address as u8 != 0 {
+                self.write_register(cs, spi, *address)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn read_error<CS, SPI, CsE, SpiE>(
         &mut self,
         cs: &mut CS,
@@ -1034,6 +1538,8 @@ impl Tdc1000 {
         cs.set_low().map_err(Error::ChipSelectError)?;
         spi.transfer(&mut read_buffer).map_err(Error::SpiError)?;
         cs.set_high().map_err(Error::ChipSelectError)?;
+        self.tracer
+            .on_access(address_to_read, Direction::Read, read_buffer[1]);
         Ok(read_buffer[1])
     }
 
@@ -1050,6 +1556,9 @@ impl Tdc1000 {
         cs.set_low().map_err(Error::ChipSelectError)?;
         spi.write(&data).map_err(Error::SpiError)?;
         cs.set_high().map_err(Error::ChipSelectError)?;
+        let address = data[0] & !SPI_WRITE_BIT;
+        self.dirty &= !(1 << address);
+        self.tracer.on_access(address, Direction::Write, data[1]);
         Ok(())
     }
 }
@@ -1066,17 +1575,17 @@ fn trim_value_u8(value: u8, max: u8, min: u8) -> u8 {
 mod tests {
     extern crate std;
     use crate::{
-        ChannelSwap, EchoQualificationThreshold, MeasurementCycles,
-        ReceiveEventsCnt, ShortTofBlankPeriod, TOFMeasurementMode, Tdc1000,
-        TimeOfFlightValue, TxFrequencyDivider, TxPulseShiftPosition, TxPulses,
-        VoltageReference,
+        ChannelSwap, EchoQualificationThreshold, Error, MeasurementCycles,
+        OutOfRange, Pin, ReceiveEventsCnt, ShortTofBlankPeriod,
+        TOFMeasurementMode, Tdc1000, TimeOfFlightValue, TxFrequencyDivider,
+        TxPulseShiftPosition, TxPulses, VoltageReference,
     };
 
     #[test]
     fn config_0_value_for_spi_is_calculated_correctly() {
         let mut tdc1000 = Tdc1000::default();
         tdc1000.set_tx_frequency_divider(TxFrequencyDivider::DivideBy8);
-        tdc1000.set_number_of_tx_pulses(TxPulses::new(1));
+        tdc1000.set_number_of_tx_pulses(TxPulses::new_const(1));
         let config0val = tdc1000.get_config_0_value();
         assert_eq!(config0val, 65);
     }
@@ -1115,7 +1624,7 @@ mod tests {
     #[test]
     fn config_4_value_for_spi_is_calculated_correctly() {
         let mut tdc1000 = Tdc1000::default();
-        tdc1000.set_tx_pulse_shift_position(TxPulseShiftPosition::new(5));
+        tdc1000.set_tx_pulse_shift_position(TxPulseShiftPosition::new_const(5));
         let config4val = tdc1000.get_config_4_value();
         assert_eq!(config4val, 5);
     }
@@ -1123,7 +1632,7 @@ mod tests {
     #[test]
     fn tof_1_value_for_spi_is_calculated_correctly() {
         let mut tdc1000 = Tdc1000::default();
-        tdc1000.set_time_of_flight(TimeOfFlightValue::new(
+        tdc1000.set_time_of_flight(TimeOfFlightValue::new_const(
             TimeOfFlightValue::HIGH,
         ));
         let tof1val = tdc1000.get_tof_1_value();
@@ -1133,7 +1642,7 @@ mod tests {
     #[test]
     fn tof_2_value_for_spi_is_calculated_correctly() {
         let mut tdc1000 = Tdc1000::default();
-        tdc1000.set_time_of_flight(TimeOfFlightValue::new(
+        tdc1000.set_time_of_flight(TimeOfFlightValue::new_const(
             TimeOfFlightValue::HIGH,
         ));
         let tof2val = tdc1000.get_tof_0_value();
@@ -1154,4 +1663,48 @@ mod tests {
         let clock_rate_value = tdc1000.get_clock_rate_value();
         assert_eq!(clock_rate_value, 0);
     }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert_eq!(TxPulses::try_new(31).map(|p| p.get_value()), Ok(31));
+        assert_eq!(
+            TxPulses::try_new(40),
+            Err(OutOfRange {
+                value: 40,
+                low: 0,
+                high: 31,
+            })
+        );
+        assert!(TxPulseShiftPosition::try_new(32).is_err());
+        assert!(TimeOfFlightValue::try_new(1024).is_err());
+        assert_eq!(TxPulses::new_clamped(40).get_value(), 31);
+        assert_eq!(
+            std::format!("{}", TxPulses::try_new(40).unwrap_err()),
+            "40 is outside of 0..=31"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn const_constructor_panics_at_runtime() {
+        let pulses = std::hint::black_box(40);
+        TxPulses::new_const(pulses);
+    }
+
+    #[test]
+    fn errors_are_displayed() {
+        let error: Error<(), u8, ()> = Error::PinError(Pin::Reset, ());
+        assert_eq!(std::format!("{}", error), "Reset pin error: ()");
+        let error: Error<(), u8> = Error::VerifyMismatch {
+            register: 3,
+            expected: 0x0b,
+            actual: 0x03,
+        };
+        assert_eq!(
+            std::format!("{}", error),
+            "register 0x03 reads 0x03 instead of 0x0b"
+        );
+        let error: Error<(), u8, ()> = Error::SpiError(7).widen();
+        assert_eq!(std::format!("{}", error), "SPI error: 7");
+    }
 }
@@ -0,0 +1,299 @@
+//! Time-of-flight capture abstraction.
+//!
+//! The TDC1000 only conditions the transducer signals, the time between the
+//! START and STOP pulses has to be measured by the MCU (timer input capture)
+//! or by a time-to-digital converter like the TDC7200. The application
+//! implements [`TofCapture`] for that part so the algorithms in this crate can
+//! run measurements on their own.
+
+use crate::trace::SpiTrace;
+use crate::{
+    ErrNoSignalRead, ErrSignalHighRead, ErrSignalWeakRead, Error,
+    ErrorFlagsRead, ErrorFlagsWrite, Tdc1000,
+};
+use core::convert::Infallible;
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+pub const MAX_STOP_EVENTS: usize = 7;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TofMeasurement {
+    stops: [f32; MAX_STOP_EVENTS],
+    stop_count: u8,
+    error_flags: Option<ErrorFlagsRead>,
+}
+
+impl TofMeasurement {
+    /// Creates a measurement from the STOP times in seconds after START.
+    /// STOP times beyond [`MAX_STOP_EVENTS`] are ignored.
+    pub fn new(stops: &[f32]) -> Self {
+        let mut measurement = TofMeasurement::default();
+        for stop in stops {
+            measurement.push_stop(*stop);
+        }
+        measurement
+    }
+
+    /// A measurement where no STOP pulse arrived.
+    pub fn timeout() -> Self {
+        TofMeasurement::default()
+    }
+
+    pub fn push_stop(&mut self, stop: f32) -> bool {
+        if (self.stop_count as usize) < MAX_STOP_EVENTS {
+            self.stops[self.stop_count as usize] = stop;
+            self.stop_count += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn stops(&self) -> &[f32] {
+        &self.stops[..self.stop_count as usize]
+    }
+
+    pub fn first_stop(&self) -> Option<f32> {
+        self.stops().first().copied()
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.stop_count == 0
+    }
+
+    /// Error flags set during this measurement, `None` if no flag was set.
+    pub fn error_flags(&self) -> Option<&ErrorFlagsRead> {
+        self.error_flags.as_ref()
+    }
+
+    pub fn record_error(&mut self, error_flags: ErrorFlagsRead) {
+        self.error_flags = Some(error_flags);
+    }
+}
+
+pub trait TofCapture {
+    type Error;
+
+    /// Fires TRIGGER and captures START and the following STOP pulses.
+    /// Returns [`TofMeasurement::timeout`] if no STOP pulse arrived.
+    ///
+    /// Implementations must bound the time they wait, e.g. by a timer
+    /// compare or a cycle counter, and return [`TofMeasurement::timeout`]
+    /// when it expires. The driver can not interrupt a blocked capture, so a
+    /// capture that waits forever for a pulse wedges every caller including
+    /// [`crate::supervisor::MeasurementSupervisor`].
+    fn capture(&mut self) -> Result<TofMeasurement, Self::Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MeasureError<CsE, SpiE, CapE, PinE = Infallible> {
+    DriverError(Error<CsE, SpiE, PinE>),
+    CaptureError(CapE),
+}
+
+impl<CsE, SpiE, CapE> MeasureError<CsE, SpiE, CapE> {
+    /// See [`Error::widen`].
+    pub fn widen<PinE>(self) -> MeasureError<CsE, SpiE, CapE, PinE> {
+        match self {
+            MeasureError::DriverError(error) => {
+                MeasureError::DriverError(error.widen())
+            }
+            MeasureError::CaptureError(error) => {
+                MeasureError::CaptureError(error)
+            }
+        }
+    }
+}
+
+impl<CsE, SpiE, CapE, PinE> From<Error<CsE, SpiE, PinE>>
+    for MeasureError<CsE, SpiE, CapE, PinE>
+{
+    fn from(error: Error<CsE, SpiE, PinE>) -> Self {
+        MeasureError::DriverError(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorStatistics {
+    pub measurements: u16,
+    pub signal_weak: u16,
+    pub no_signal: u16,
+    pub signal_high: u16,
+    /// Measurements with at least one error flag set.
+    pub erroneous: u16,
+}
+
+impl ErrorStatistics {
+    /// Records the flags of one measurement and returns `true` if any
+    /// error flag was set.
+    pub fn record(&mut self, error_flags: &ErrorFlagsRead) -> bool {
+        self.measurements = self.measurements.saturating_add(1);
+        let weak =
+            *error_flags.signal_week() == ErrSignalWeakRead::SignalWeekTimeout;
+        let no_signal =
+            *error_flags.no_signal() == ErrNoSignalRead::NoSignalTimeout;
+        let high = *error_flags.signal_high() == ErrSignalHighRead::SignalHigh;
+        self.signal_weak = self.signal_weak.saturating_add(weak as u16);
+        self.no_signal = self.no_signal.saturating_add(no_signal as u16);
+        self.signal_high = self.signal_high.saturating_add(high as u16);
+        let erroneous = error_flags.has_error();
+        self.erroneous = self.erroneous.saturating_add(erroneous as u16);
+        erroneous
+    }
+}
+
+/// Running mean and standard deviation of TOF values.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TofStatistics {
+    count: u16,
+    mean: f32,
+    sum_of_squares: f32,
+}
+
+impl TofStatistics {
+    pub fn add(&mut self, tof: f32) {
+        self.count = self.count.saturating_add(1);
+        let delta = tof - self.mean;
+        self.mean += delta / self.count as f32;
+        self.sum_of_squares += delta * (tof - self.mean);
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        if self.count > 0 {
+            Some(self.mean)
+        } else {
+            None
+        }
+    }
+
+    /// Standard deviation of the TOF values in seconds.
+    pub fn jitter(&self) -> Option<f32> {
+        if self.count > 1 {
+            Some(libm::sqrtf(self.sum_of_squares / (self.count - 1) as f32))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeasurementStatistics {
+    pub errors: ErrorStatistics,
+    pub tof: TofStatistics,
+    /// Measurements with a STOP pulse and without error flags.
+    pub successes: u16,
+}
+
+impl MeasurementStatistics {
+    pub fn success_rate(&self) -> f32 {
+        if self.errors.measurements == 0 {
+            0.0
+        } else {
+            self.successes as f32 / self.errors.measurements as f32
+        }
+    }
+
+    pub fn collect<T, CS, SPI, C, CsE, SpiE>(
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+        measurements: u16,
+    ) -> Result<Self, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let mut statistics = MeasurementStatistics::default();
+        for _ in 0..measurements {
+            let measurement = tdc1000.measure(cs, spi, capture)?;
+            let error_flags = measurement.error_flags().copied();
+            let erroneous =
+                statistics.errors.record(&error_flags.unwrap_or_default());
+            if let (false, Some(tof)) = (erroneous, measurement.first_stop()) {
+                statistics.successes += 1;
+                statistics.tof.add(tof);
+            }
+        }
+        Ok(statistics)
+    }
+}
+
+impl<T: SpiTrace> Tdc1000<T> {
+    /// Captures one measurement, reads the error flags and attaches them to
+    /// the measurement if any flag is set. Set flags are cleared, a timed out
+    /// measurement also resets the state machine, which may still wait for a
+    /// STOP pulse.
+    pub fn measure<CS, SPI, C, CsE, SpiE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+    ) -> Result<TofMeasurement, MeasureError<CsE, SpiE, C::Error>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let mut measurement =
+            capture.capture().map_err(MeasureError::CaptureError)?;
+        self.check_errors(cs, spi, &mut measurement)?;
+        Ok(measurement)
+    }
+
+    /// Reads the error flags after `measurement` and attaches them if any
+    /// flag is set. Set flags are cleared, a timed out measurement also
+    /// resets the state machine, which may still wait for a STOP pulse.
+    pub(crate) fn check_errors<CS, SPI, CsE, SpiE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+        measurement: &mut TofMeasurement,
+    ) -> Result<(), Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        let error_flags = self.read_error(cs, spi)?;
+        if error_flags.has_error() {
+            measurement.record_error(error_flags);
+        }
+        if let Some(reset) = error_reset(measurement.is_timeout(), &error_flags)
+        {
+            self.reset_error(cs, spi, reset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reset needed after a measurement with `error_flags`.
+pub(crate) fn error_reset(
+    timed_out: bool,
+    error_flags: &ErrorFlagsRead,
+) -> Option<ErrorFlagsWrite> {
+    if timed_out {
+        Some(ErrorFlagsWrite::ResetErrorStatemachineAndMeasurement)
+    } else if error_flags.has_error() {
+        Some(ErrorFlagsWrite::ResetAllErrorFlagsAndErrorPin)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,334 @@
+//! Poll driven measurements for super loops and `nb` based HALs.
+//!
+//! [`NbMeasurement::poll`] never waits for the echo. It triggers the
+//! measurement if none is running and returns [`nb::Error::WouldBlock`] until
+//! the [`NbCapture`] has seen START and all STOP pulses or timed out. The
+//! error flags are then read, and cleared together with a state machine
+//! reset if needed, before the measurement is returned. If that fails, the
+//! next poll retries the check instead of triggering a new measurement.
+//!
+//! ```
+//! # use tdc1000::measurement::TofMeasurement;
+//! # use tdc1000::nonblocking::{NbCapture, NbMeasurement};
+//! # use tdc1000::simulator::Simulator;
+//! # use tdc1000::Tdc1000;
+//! # struct Capture(u8);
+//! # impl NbCapture for Capture {
+//! #     type Error = ();
+//! #     fn trigger(&mut self) -> Result<(), ()> {
+//! #         self.0 = 3;
+//! #         Ok(())
+//! #     }
+//! #     fn poll(&mut self) -> nb::Result<TofMeasurement, ()> {
+//! #         self.0 -= 1;
+//! #         if self.0 > 0 {
+//! #             return Err(nb::Error::WouldBlock);
+//! #         }
+//! #         Ok(TofMeasurement::new(&[40e-6]))
+//! #     }
+//! # }
+//! let simulator = Simulator::new();
+//! let (mut cs, mut spi) = (simulator.cs(), simulator.spi());
+//! let mut tdc1000 = Tdc1000::default();
+//! let mut measurement = NbMeasurement::new(Capture(0));
+//! let tof = loop {
+//!     match measurement.poll(&mut tdc1000, &mut cs, &mut spi) {
+//!         Ok(tof) => break tof,
+//!         Err(nb::Error::WouldBlock) => { /* other super loop tasks */ }
+//!         Err(nb::Error::Other(error)) => panic!("{:?}", error),
+//!     }
+//! };
+//! assert_eq!(tof.first_stop(), Some(40e-6));
+//! ```
+
+use crate::measurement::{MeasureError, TofMeasurement};
+use crate::trace::SpiTrace;
+use crate::Tdc1000;
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+/// Non-blocking counterpart of [`crate::measurement::TofCapture`].
+pub trait NbCapture {
+    type Error;
+
+    /// Fires TRIGGER and arms the capture of START and STOP pulses.
+    fn trigger(&mut self) -> Result<(), Self::Error>;
+
+    /// Returns the measurement once START and the STOP pulses arrived, or
+    /// [`TofMeasurement::timeout`] if no STOP pulse arrived in time.
+    fn poll(&mut self) -> nb::Result<TofMeasurement, Self::Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MeasurementState {
+    Idle,
+    /// Triggered, waiting for START and STOP.
+    Capturing,
+    /// Captured, the error flags still have to be read and cleared.
+    Checking,
+}
+
+pub struct NbMeasurement<C> {
+    capture: C,
+    state: MeasurementState,
+    /// Captured measurement while [`MeasurementState::Checking`].
+    captured: TofMeasurement,
+}
+
+impl<C: NbCapture> NbMeasurement<C> {
+    pub fn new(capture: C) -> Self {
+        NbMeasurement {
+            capture,
+            state: MeasurementState::Idle,
+            captured: TofMeasurement::default(),
+        }
+    }
+
+    pub fn state(&self) -> MeasurementState {
+        self.state
+    }
+
+    pub fn release(self) -> C {
+        self.capture
+    }
+
+    /// Triggers a new measurement, abandoning a running one or a pending
+    /// error check.
+    pub fn start(&mut self) -> Result<(), C::Error> {
+        self.state = MeasurementState::Idle;
+        self.capture.trigger()?;
+        self.state = MeasurementState::Capturing;
+        Ok(())
+    }
+
+    /// Advances the measurement, starting one if idle. Error flags set
+    /// during the measurement are attached to it. The state is idle again
+    /// after the measurement completed or the capture failed. If reading or
+    /// clearing the error flags fails, the state stays
+    /// [`MeasurementState::Checking`] and the next poll retries the check.
+    pub fn poll<T, CS, SPI, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+    ) -> nb::Result<TofMeasurement, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        if self.state == MeasurementState::Idle {
+            self.start().map_err(MeasureError::CaptureError)?;
+        }
+        if self.state == MeasurementState::Capturing {
+            self.captured = match self.capture.poll() {
+                Ok(measurement) => measurement,
+                Err(nb::Error::WouldBlock) => {
+                    return Err(nb::Error::WouldBlock)
+                }
+                Err(nb::Error::Other(error)) => {
+                    self.state = MeasurementState::Idle;
+                    return Err(nb::Error::Other(MeasureError::CaptureError(
+                        error,
+                    )));
+                }
+            };
+            self.state = MeasurementState::Checking;
+        }
+        let mut measurement = self.captured;
+        tdc1000
+            .check_errors(cs, spi, &mut measurement)
+            .map_err(MeasureError::DriverError)?;
+        self.state = MeasurementState::Idle;
+        Ok(measurement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::measurement::{MeasureError, TofMeasurement};
+    use crate::nonblocking::{MeasurementState, NbCapture, NbMeasurement};
+    use crate::simulator::{SimulatedSpi, Simulator, SimulatorError};
+    use crate::{ErrNoSignalRead, Error, Tdc1000};
+    use hal::blocking::spi::{Transfer, Write};
+
+    /// Completes after `polls` polls and raises `error_flags` in the
+    /// simulator.
+    struct FakeCapture<'a> {
+        simulator: &'a Simulator,
+        polls: u8,
+        remaining: u8,
+        triggers: u8,
+        stop: Option<f32>,
+        error_flags: u8,
+    }
+
+    impl<'a> FakeCapture<'a> {
+        fn new(simulator: &'a Simulator, stop: Option<f32>) -> Self {
+            FakeCapture {
+                simulator,
+                polls: 3,
+                remaining: 0,
+                triggers: 0,
+                stop,
+                error_flags: 0,
+            }
+        }
+    }
+
+    impl NbCapture for FakeCapture<'_> {
+        type Error = ();
+
+        fn trigger(&mut self) -> Result<(), ()> {
+            self.triggers += 1;
+            self.remaining = self.polls;
+            Ok(())
+        }
+
+        fn poll(&mut self) -> nb::Result<TofMeasurement, ()> {
+            match self.remaining {
+                0 => Err(nb::Error::Other(())),
+                1 => {
+                    self.remaining = 0;
+                    self.simulator.set_register(7, self.error_flags);
+                    Ok(match self.stop {
+                        Some(stop) => TofMeasurement::new(&[stop]),
+                        None => TofMeasurement::timeout(),
+                    })
+                }
+                _ => {
+                    self.remaining -= 1;
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+    }
+
+    /// Fails the first `failures` writes.
+    struct FlakySpi<'a> {
+        spi: SimulatedSpi<'a>,
+        failures: u8,
+    }
+
+    impl Transfer<u8> for FlakySpi<'_> {
+        type Error = SimulatorError;
+
+        fn transfer<'w>(
+            &mut self,
+            words: &'w mut [u8],
+        ) -> Result<&'w [u8], SimulatorError> {
+            self.spi.transfer(words)
+        }
+    }
+
+    impl Write<u8> for FlakySpi<'_> {
+        type Error = SimulatorError;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), SimulatorError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(SimulatorError::ChipNotSelected);
+            }
+            self.spi.write(words)
+        }
+    }
+
+    #[test]
+    fn measurement_progresses_on_poll() {
+        let simulator = Simulator::new();
+        let (mut cs, mut spi) = (simulator.cs(), simulator.spi());
+        let mut tdc1000 = Tdc1000::default();
+        let mut measurement =
+            NbMeasurement::new(FakeCapture::new(&simulator, Some(30e-6)));
+        for _ in 0..2 {
+            assert_eq!(
+                measurement.poll(&mut tdc1000, &mut cs, &mut spi),
+                Err(nb::Error::WouldBlock)
+            );
+            assert_eq!(measurement.state(), MeasurementState::Capturing);
+        }
+        let tof = measurement.poll(&mut tdc1000, &mut cs, &mut spi).unwrap();
+        assert_eq!(tof.first_stop(), Some(30e-6));
+        assert_eq!(tof.error_flags(), None);
+        assert_eq!(measurement.state(), MeasurementState::Idle);
+        assert_eq!(simulator.state_machine_resets(), 0);
+        assert_eq!(measurement.release().triggers, 1);
+    }
+
+    #[test]
+    fn timeout_resets_flags_and_state_machine() {
+        let simulator = Simulator::new();
+        let (mut cs, mut spi) = (simulator.cs(), simulator.spi());
+        let mut tdc1000 = Tdc1000::default();
+        let mut capture = FakeCapture::new(&simulator, None);
+        capture.polls = 1;
+        capture.error_flags = 0b010;
+        let mut measurement = NbMeasurement::new(capture);
+        measurement.start().unwrap();
+        let tof = measurement.poll(&mut tdc1000, &mut cs, &mut spi).unwrap();
+        assert!(tof.is_timeout());
+        assert_eq!(
+            *tof.error_flags().unwrap().no_signal(),
+            ErrNoSignalRead::NoSignalTimeout
+        );
+        assert_eq!(simulator.register(7), 0);
+        assert_eq!(simulator.state_machine_resets(), 1);
+        assert_eq!(measurement.release().triggers, 1);
+    }
+
+    #[test]
+    fn capture_errors_return_to_idle() {
+        let simulator = Simulator::new();
+        let mut capture = FakeCapture::new(&simulator, Some(30e-6));
+        capture.polls = 0;
+        let mut measurement = NbMeasurement::new(capture);
+        assert_eq!(
+            measurement.poll(
+                &mut Tdc1000::default(),
+                &mut simulator.cs(),
+                &mut simulator.spi()
+            ),
+            Err(nb::Error::Other(MeasureError::CaptureError(())))
+        );
+        assert_eq!(measurement.state(), MeasurementState::Idle);
+    }
+
+    #[test]
+    fn failed_error_reset_is_retried() {
+        let simulator = Simulator::new();
+        let mut cs = simulator.cs();
+        let mut spi = FlakySpi {
+            spi: simulator.spi(),
+            failures: 1,
+        };
+        let mut tdc1000 = Tdc1000::default();
+        let mut capture = FakeCapture::new(&simulator, None);
+        capture.polls = 1;
+        capture.error_flags = 0b010;
+        let mut measurement = NbMeasurement::new(capture);
+        assert_eq!(
+            measurement.poll(&mut tdc1000, &mut cs, &mut spi),
+            Err(nb::Error::Other(MeasureError::DriverError(
+                Error::SpiError(SimulatorError::ChipNotSelected)
+            )))
+        );
+        assert_eq!(measurement.state(), MeasurementState::Checking);
+        assert_eq!(simulator.state_machine_resets(), 0);
+
+        let tof = measurement.poll(&mut tdc1000, &mut cs, &mut spi).unwrap();
+        assert!(tof.is_timeout());
+        assert_eq!(
+            *tof.error_flags().unwrap().no_signal(),
+            ErrNoSignalRead::NoSignalTimeout
+        );
+        assert_eq!(measurement.state(), MeasurementState::Idle);
+        assert_eq!(simulator.register(7), 0);
+        assert_eq!(simulator.state_machine_resets(), 1);
+        assert_eq!(measurement.release().triggers, 1);
+    }
+}
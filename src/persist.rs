@@ -0,0 +1,605 @@
+//! Compact binary configuration blob for storage in MCU flash.
+//!
+//! The blob stores the nine configuration registers in their on-chip
+//! encoding, which is fixed by the hardware and therefore stable across
+//! driver versions, followed by the optional CLKIN frequency, calibration
+//! data and channel profiles. Layout of format version 2, multi byte values
+//! are little endian:
+//!
+//! | offset | size | content                                          |
+//! |--------|------|--------------------------------------------------|
+//! | 0      | 1    | format version                                   |
+//! | 1      | 1    | flags, bit 0: CLKIN present, bit 1: calibration, |
+//! |        |      | bit 2: channel profiles                          |
+//! | 2      | 9    | CONFIG_0..4, TOF_1, TOF_0, TIMEOUT, CLOCK_RATE    |
+//! | 11     | 4    | CLKIN in Hz, `f32`                               |
+//! | 15     | 4    | zero flow offset in seconds, `f32`               |
+//! | 19     | 4    | scale factor, `f32`                              |
+//! | 23     | 2    | channel 1 profile                                |
+//! | 25     | 2    | channel 2 profile                                |
+//! | 27     | 2    | CRC-16/CCITT-FALSE over bytes 0..27              |
+//!
+//! A profile is stored as the TX pulse count followed by the PGA gain in
+//! bits 5..3 and the echo qualification threshold in bits 2..0, the same
+//! encodings as in the registers.
+//!
+//! Format version 1 has no channel profiles and flag bit 2, its CRC follows
+//! the scale factor at offset 23 (25 bytes in total).
+//!
+//! [`StoredConfig::from_bytes`] decodes every listed format version, so
+//! `StoredConfig::from_bytes(&old)?.to_bytes()` migrates a stored blob to
+//! the current format. A layout change bumps [`FORMAT_VERSION`], documents
+//! the previous layout here and adds a decoder for it.
+
+use crate::profile::ChannelProfile;
+use crate::trace::NoTrace;
+use crate::{
+    AmplifierAndTimeOfFlight, AmplifierControl, AutoZeroPeriod, ChannelSelect,
+    ChannelSwap, ClockInDiv, ClockRate, Config0, Config1, Config2, Config3,
+    Config4, ConfigAddresses, DampingMode, EchoTimeout, ExternalChannelSelect,
+    ForceShortTimeOfFlight, LnaFeedbackMode, MeasurementCycles,
+    MeasurementMode, PowerBlanking, ReceiveEventsCnt, ReceiveMode,
+    ShortTofBlankPeriod, TOFMeasurementMode, Tdc1000, TempClockDivider,
+    TempMode, TempRtdSelect, TimeOfFlightValue, TimeOut, TofTimeoutControl,
+    TriggerEdgePolarity, TxFrequencyDivider, TxPulseShiftPosition, TxPulses,
+    VoltageReference, ALL_REGISTERS_DIRTY, CONFIG_REGISTERS, ECHO_THRESHOLDS,
+    PGA_GAINS,
+};
+
+pub const FORMAT_VERSION: u8 = 2;
+pub const BLOB_LEN: usize = 29;
+const BLOB_LEN_V1: usize = 25;
+const REGISTER_COUNT: usize = CONFIG_REGISTERS.len();
+
+const FLAG_CLKIN: u8 = 0b01;
+const FLAG_CALIBRATION: u8 = 0b10;
+const FLAG_PROFILES: u8 = 0b100;
+
+/// Bits that must be zero in the stored registers.
+const RESERVED_BITS: [u8; REGISTER_COUNT] =
+    [0x00, 0x00, 0x00, 0x80, 0x80, 0x00, 0x00, 0x80, 0xf8];
+
+const TX_FREQUENCY_DIVIDERS: [TxFrequencyDivider; 8] = [
+    TxFrequencyDivider::DivideBy2,
+    TxFrequencyDivider::DivideBy4,
+    TxFrequencyDivider::DivideBy8,
+    TxFrequencyDivider::DivideBy16,
+    TxFrequencyDivider::DivideBy32,
+    TxFrequencyDivider::DivideBy64,
+    TxFrequencyDivider::DivideBy128,
+    TxFrequencyDivider::DivideBy256,
+];
+
+const MEASUREMENT_CYCLES: [MeasurementCycles; 8] = [
+    MeasurementCycles::MeasurementCycles1,
+    MeasurementCycles::MeasurementCycles2,
+    MeasurementCycles::MeasurementCycles4,
+    MeasurementCycles::MeasurementCycles8,
+    MeasurementCycles::MeasurementCycles16,
+    MeasurementCycles::MeasurementCycles32,
+    MeasurementCycles::MeasurementCycles64,
+    MeasurementCycles::MeasurementCycles128,
+];
+
+const RECEIVE_EVENTS: [ReceiveEventsCnt; 8] = [
+    ReceiveEventsCnt::DoNotCountStopEvents,
+    ReceiveEventsCnt::StopEvents1,
+    ReceiveEventsCnt::StopEvents2,
+    ReceiveEventsCnt::StopEvents3,
+    ReceiveEventsCnt::StopEvents4,
+    ReceiveEventsCnt::StopEvents5,
+    ReceiveEventsCnt::StopEvents6,
+    ReceiveEventsCnt::StopEvents7,
+];
+
+const TOF_MEASUREMENT_MODES: [TOFMeasurementMode; 3] = [
+    TOFMeasurementMode::Mode0,
+    TOFMeasurementMode::Mode1,
+    TOFMeasurementMode::Mode2,
+];
+
+const SHORT_TOF_BLANK_PERIODS: [ShortTofBlankPeriod; 8] = [
+    ShortTofBlankPeriod::T0Times8,
+    ShortTofBlankPeriod::T0Times16,
+    ShortTofBlankPeriod::T0Times32,
+    ShortTofBlankPeriod::T0Times64,
+    ShortTofBlankPeriod::T0Times128,
+    ShortTofBlankPeriod::T0Times256,
+    ShortTofBlankPeriod::T0Times512,
+    ShortTofBlankPeriod::T0Times1024,
+];
+
+const TOF_TIMEOUTS: [TofTimeoutControl; 4] = [
+    TofTimeoutControl::T0Times128,
+    TofTimeoutControl::T0Times256,
+    TofTimeoutControl::T0Times512,
+    TofTimeoutControl::T0Times1024,
+];
+
+const AUTO_ZERO_PERIODS: [AutoZeroPeriod; 4] = [
+    AutoZeroPeriod::T0Times64,
+    AutoZeroPeriod::T0Times128,
+    AutoZeroPeriod::T0Times256,
+    AutoZeroPeriod::T0Times512,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PersistError {
+    InvalidLength(usize),
+    UnsupportedVersion(u8),
+    CrcMismatch,
+    /// A channel profile holds a reserved bit pattern.
+    InvalidProfile,
+    /// A register holds a reserved bit pattern, `register` is the register
+    /// address.
+    InvalidRegister {
+        register: u8,
+        value: u8,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Calibration {
+    /// TOF difference measured at zero flow in seconds.
+    pub zero_flow_offset: f32,
+    /// Meter factor applied to the flow derived from the TOF.
+    pub scale: f32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            zero_flow_offset: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StoredConfig {
+    pub tdc1000: Tdc1000,
+    /// CLKIN frequency in Hz.
+    pub clkin: Option<f32>,
+    pub calibration: Option<Calibration>,
+}
+
+impl StoredConfig {
+    pub fn new(tdc1000: Tdc1000) -> Self {
+        StoredConfig {
+            tdc1000,
+            clkin: None,
+            calibration: None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; BLOB_LEN] {
+        let mut bytes = [0; BLOB_LEN];
+        bytes[0] = FORMAT_VERSION;
+        for (byte, address) in
+            bytes[2..11].iter_mut().zip(CONFIG_REGISTERS.iter())
+        {
+            *byte = self.tdc1000.get_register_value(*address);
+        }
+        if let Some(clkin) = self.clkin {
+            bytes[1] |= FLAG_CLKIN;
+            bytes[11..15].copy_from_slice(&clkin.to_le_bytes());
+        }
+        if let Some(calibration) = self.calibration {
+            bytes[1] |= FLAG_CALIBRATION;
+            bytes[15..19]
+                .copy_from_slice(&calibration.zero_flow_offset.to_le_bytes());
+            bytes[19..23].copy_from_slice(&calibration.scale.to_le_bytes());
+        }
+        if let Some(profiles) = &self.tdc1000.profiles {
+            bytes[1] |= FLAG_PROFILES;
+            for (chunk, profile) in bytes[23..27].chunks_mut(2).zip(profiles) {
+                chunk[0] = profile.tx_pulses.get_value();
+                chunk[1] = (profile.pga_gain as u8) << 3
+                    | profile.echo_qualification_threshold as u8;
+            }
+        }
+        let crc = crc16(&bytes[..BLOB_LEN - 2]);
+        bytes[BLOB_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a blob of the current or an older format version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PersistError> {
+        let version = *bytes.first().ok_or(PersistError::InvalidLength(0))?;
+        let expected_len = match version {
+            1 => BLOB_LEN_V1,
+            FORMAT_VERSION => BLOB_LEN,
+            _ => return Err(PersistError::UnsupportedVersion(version)),
+        };
+        if bytes.len() != expected_len {
+            return Err(PersistError::InvalidLength(bytes.len()));
+        }
+        let (data, crc) = bytes.split_at(expected_len - 2);
+        if crc16(data).to_le_bytes() != crc {
+            return Err(PersistError::CrcMismatch);
+        }
+
+        match version {
+            1 => decode_v1(data),
+            _ => decode_v2(data),
+        }
+    }
+}
+
+/// Registers, CLKIN and calibration. Version 2 keeps these offsets.
+fn decode_v1(data: &[u8]) -> Result<StoredConfig, PersistError> {
+    let flags = data[1];
+    let mut register_values = [0; REGISTER_COUNT];
+    register_values.copy_from_slice(&data[2..11]);
+    let mut config = StoredConfig::new(decode_registers(&register_values)?);
+    if flags & FLAG_CLKIN != 0 {
+        config.clkin = Some(read_f32(&data[11..15]));
+    }
+    if flags & FLAG_CALIBRATION != 0 {
+        config.calibration = Some(Calibration {
+            zero_flow_offset: read_f32(&data[15..19]),
+            scale: read_f32(&data[19..23]),
+        });
+    }
+    Ok(config)
+}
+
+/// Version 1 followed by the channel profiles.
+fn decode_v2(data: &[u8]) -> Result<StoredConfig, PersistError> {
+    let mut config = decode_v1(data)?;
+    if data[1] & FLAG_PROFILES != 0 {
+        config.tdc1000.profiles = Some([
+            decode_profile(&data[23..25])?,
+            decode_profile(&data[25..27])?,
+        ]);
+    }
+    Ok(config)
+}
+
+fn decode_profile(bytes: &[u8]) -> Result<ChannelProfile, PersistError> {
+    if bytes[1] & 0xc0 != 0 {
+        return Err(PersistError::InvalidProfile);
+    }
+    Ok(ChannelProfile {
+        pga_gain: PGA_GAINS[(bytes[1] >> 3) as usize],
+        echo_qualification_threshold: ECHO_THRESHOLDS
+            [(bytes[1] & 0b111) as usize],
+        tx_pulses: TxPulses::try_new(bytes[0])
+            .map_err(|_| PersistError::InvalidProfile)?,
+    })
+}
+
+fn read_f32(bytes: &[u8]) -> f32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(bytes);
+    f32::from_le_bytes(buffer)
+}
+
+/// CRC-16/CCITT-FALSE, polynomial 0x1021, initial value 0xffff.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn bit(value: u8, offset: u8) -> bool {
+    value >> offset & 1 == 1
+}
+
+fn decode_registers(
+    registers: &[u8; REGISTER_COUNT],
+) -> Result<Tdc1000<NoTrace>, PersistError> {
+    for ((value, reserved), address) in registers
+        .iter()
+        .zip(RESERVED_BITS.iter())
+        .zip(CONFIG_REGISTERS.iter())
+    {
+        if value & reserved != 0 {
+            return Err(PersistError::InvalidRegister {
+                register: *address as u8,
+                value: *value,
+            });
+        }
+    }
+    let [config0, config1, config2, config3, config4, tof1, tof0, timeout, clock_rate] =
+        *registers;
+    let tof_meas_mode = *TOF_MEASUREMENT_MODES
+        .get((config2 & 0b11) as usize)
+        .ok_or(PersistError::InvalidRegister {
+        register: ConfigAddresses::Config2 as u8,
+        value: config2,
+    })?;
+
+    Ok(Tdc1000 {
+        config0: Config0 {
+            tx_frequency_divider: TX_FREQUENCY_DIVIDERS
+                [(config0 >> crate::FREQUENCY_DIVIDER_BIT_OFFSET) as usize],
+            tx_pulses: TxPulses::new_clamped(config0 & 0x1f),
+        },
+        config1: Config1 {
+            measurement_cycles: MEASUREMENT_CYCLES[(config1
+                >> crate::MEASUREMENT_CYCLES_BIT_OFFSET
+                & 0b111)
+                as usize],
+            receive_events_cnt: RECEIVE_EVENTS[(config1 & 0b111) as usize],
+        },
+        config2: Config2 {
+            voltage_reference: if bit(
+                config2,
+                crate::VOLTAGE_REFERENCE_BIT_OFFSET,
+            ) {
+                VoltageReference::External
+            } else {
+                VoltageReference::Internal
+            },
+            measurement_mode: if bit(
+                config2,
+                crate::MEASUREMENT_MODE_BIT_OFFSET,
+            ) {
+                MeasurementMode::Temperature
+            } else {
+                MeasurementMode::TimeOfFlight
+            },
+            damping_mode: if bit(config2, crate::DAMPING_MODE_BIT_OFFSET) {
+                DampingMode::EnableDamping
+            } else {
+                DampingMode::DisableDamping
+            },
+            channel_swap: if bit(config2, crate::CHANNEL_SWAP_BIT_OFFSET) {
+                ChannelSwap::EnableSwap
+            } else {
+                ChannelSwap::DisableSwap
+            },
+            ext_channel_select: if bit(
+                config2,
+                crate::EXTERNAL_CHANNEL_SELECT_BIT_OFFSET,
+            ) {
+                ExternalChannelSelect::EnableExternalChannelSelect
+            } else {
+                ExternalChannelSelect::DisableExternalChannelSelect
+            },
+            channel_select: if bit(config2, crate::CHANNEL_SELECT_BIT_OFFSET) {
+                ChannelSelect::Channel2
+            } else {
+                ChannelSelect::Channel1
+            },
+            tof_meas_mode,
+        },
+        config3: Config3 {
+            temp_mode: if bit(config3, crate::TEMP_MODE_BIT_OFFSET) {
+                TempMode::MeasureRefRtd1
+            } else {
+                TempMode::MeasureRefRtd1Rtd2
+            },
+            temp_rtd: if bit(config3, crate::TEMP_RTD_SELECT_BIT_OFFSET) {
+                TempRtdSelect::PT500
+            } else {
+                TempRtdSelect::PT1000
+            },
+            temp_clk_div: if bit(config3, crate::TEMP_CLK_DIV_BIT_OFFSET) {
+                TempClockDivider::UseTxFreqDivider
+            } else {
+                TempClockDivider::DivideBy8
+            },
+            blanking: if bit(config3, crate::BLANKING_BIT_OFFSET) {
+                PowerBlanking::EnablePowerBlanking
+            } else {
+                PowerBlanking::DisablePowerBlanking
+            },
+            echo_qualification_threshold: ECHO_THRESHOLDS
+                [(config3 & 0b111) as usize],
+        },
+        config4: Config4 {
+            receive_mode: if bit(config4, crate::RECEIVE_MODE_BIT_OFFSET) {
+                ReceiveMode::MultiEcho
+            } else {
+                ReceiveMode::SingleEcho
+            },
+            trigger_edge_polarity: if bit(
+                config4,
+                crate::TRIGGER_EDGE_POLARITY_BIT_OFFSET,
+            ) {
+                TriggerEdgePolarity::FallingEdge
+            } else {
+                TriggerEdgePolarity::RisingEdge
+            },
+            tx_pulse_shift_position: TxPulseShiftPosition::new_clamped(
+                config4 & 0x1f,
+            ),
+        },
+        amplifier_and_time_of_flight: AmplifierAndTimeOfFlight {
+            pga_gain: PGA_GAINS[(tof1 >> crate::PGA_GAIN_BIT_OFFSET) as usize],
+            pga_ctrl: amplifier_control(bit(tof1, crate::PGA_CTRL_BIT_OFFSET)),
+            lna_ctrl: amplifier_control(bit(tof1, crate::LNA_CTRL_BIT_OFFSET)),
+            lna_fb: if bit(tof1, crate::LNA_FB_BIT_OFFSET) {
+                LnaFeedbackMode::ResistiveMode
+            } else {
+                LnaFeedbackMode::CapacitiveMode
+            },
+            time_of_flight: TimeOfFlightValue::new_clamped(
+                ((tof1 & 0b11) as u16) << 8 | tof0 as u16,
+            ),
+        },
+        timeout: TimeOut {
+            force_short_tof: if bit(timeout, crate::FORCE_SHORT_TOF_BIT_OFFSET)
+            {
+                ForceShortTimeOfFlight::ForceShortTimeOfFlight
+            } else {
+                ForceShortTimeOfFlight::Disabled
+            },
+            short_tof_blank_period: SHORT_TOF_BLANK_PERIODS[(timeout
+                >> crate::SHORT_TOF_BLANK_PERIOD_BIT_OFFSET
+                & 0b111)
+                as usize],
+            echo_timeout: if bit(timeout, crate::ECHO_TIMEOUT_BIT_OFFSET) {
+                EchoTimeout::DisableTimeout
+            } else {
+                EchoTimeout::EnableTimeout
+            },
+            tof_timeout_crl: TOF_TIMEOUTS[(timeout & 0b11) as usize],
+        },
+        clock_rate: ClockRate {
+            clock_in_div: if bit(clock_rate, crate::CLOCK_IN_DIV_BIT_OFFSET) {
+                ClockInDiv::DivideBy2
+            } else {
+                ClockInDiv::DivideBy1
+            },
+            auto_zero_period: AUTO_ZERO_PERIODS[(clock_rate & 0b11) as usize],
+        },
+        dirty: ALL_REGISTERS_DIRTY,
+        profiles: None,
+        tracer: NoTrace,
+    })
+}
+
+fn amplifier_control(bypassed: bool) -> AmplifierControl {
+    if bypassed {
+        AmplifierControl::BypassedAndPoweredOff
+    } else {
+        AmplifierControl::Active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::persist::{Calibration, PersistError, StoredConfig, BLOB_LEN};
+    use crate::profile::ChannelProfile;
+    use crate::{
+        ChannelSwap, EchoQualificationThreshold, PgaGain, TOFMeasurementMode,
+        Tdc1000, TimeOfFlightValue, TofTimeoutControl, TxPulses,
+    };
+
+    fn configured() -> Tdc1000 {
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_number_of_tx_pulses(TxPulses::new_const(17));
+        tdc1000.set_channel_swap(ChannelSwap::EnableSwap);
+        tdc1000.set_tof_meas_mode(TOFMeasurementMode::Mode2);
+        tdc1000.set_echo_qualification_threshold(
+            EchoQualificationThreshold::Mv410,
+        );
+        tdc1000.set_pga_gain(PgaGain::DB15);
+        tdc1000.set_time_of_flight(TimeOfFlightValue::new_const(0x2a5));
+        tdc1000.set_tof_timeout_ctrl(TofTimeoutControl::T0Times1024);
+        tdc1000
+    }
+
+    #[test]
+    fn configuration_round_trips() {
+        let config = StoredConfig {
+            tdc1000: configured(),
+            clkin: Some(8e6),
+            calibration: Some(Calibration {
+                zero_flow_offset: -1.5e-9,
+                scale: 1.02,
+            }),
+        };
+        let bytes = config.to_bytes();
+        assert_eq!(bytes[0], 2);
+        assert_eq!(StoredConfig::from_bytes(&bytes), Ok(config));
+
+        let config = StoredConfig::new(configured());
+        assert_eq!(StoredConfig::from_bytes(&config.to_bytes()), Ok(config));
+    }
+
+    #[test]
+    fn channel_profiles_round_trip() {
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_channel_profiles(
+            ChannelProfile {
+                pga_gain: PgaGain::DB21,
+                ..ChannelProfile::default()
+            },
+            ChannelProfile {
+                pga_gain: PgaGain::DB3,
+                echo_qualification_threshold:
+                    EchoQualificationThreshold::Mv1500,
+                tx_pulses: TxPulses::new_const(31),
+            },
+        );
+        let config = StoredConfig::new(tdc1000);
+        let mut bytes = config.to_bytes();
+        assert_eq!(StoredConfig::from_bytes(&bytes), Ok(config));
+
+        bytes[24] |= 0x40;
+        let crc = super::crc16(&bytes[..BLOB_LEN - 2]);
+        bytes[BLOB_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            StoredConfig::from_bytes(&bytes),
+            Err(PersistError::InvalidProfile)
+        );
+    }
+
+    #[test]
+    fn corrupt_blobs_are_rejected() {
+        let mut bytes = StoredConfig::new(configured()).to_bytes();
+        bytes[4] ^= 0x01;
+        assert_eq!(
+            StoredConfig::from_bytes(&bytes),
+            Err(PersistError::CrcMismatch)
+        );
+        assert_eq!(
+            StoredConfig::from_bytes(&bytes[..BLOB_LEN - 1]),
+            Err(PersistError::InvalidLength(BLOB_LEN - 1))
+        );
+        bytes[0] = 3;
+        assert_eq!(
+            StoredConfig::from_bytes(&bytes),
+            Err(PersistError::UnsupportedVersion(3))
+        );
+        assert_eq!(
+            StoredConfig::from_bytes(&[]),
+            Err(PersistError::InvalidLength(0))
+        );
+    }
+
+    #[test]
+    fn version_1_blobs_are_migrated() {
+        let config = StoredConfig {
+            tdc1000: configured(),
+            clkin: Some(4e6),
+            calibration: Some(Calibration {
+                zero_flow_offset: 2e-9,
+                scale: 0.98,
+            }),
+        };
+        let current = config.to_bytes();
+        let mut v1 = [0; 25];
+        v1[..23].copy_from_slice(&current[..23]);
+        v1[0] = 1;
+        let crc = super::crc16(&v1[..23]);
+        v1[23..].copy_from_slice(&crc.to_le_bytes());
+
+        let decoded = StoredConfig::from_bytes(&v1).unwrap();
+        assert_eq!(decoded, config);
+        assert_eq!(decoded.to_bytes(), current);
+        assert_eq!(
+            StoredConfig::from_bytes(&v1[..24]),
+            Err(PersistError::InvalidLength(24))
+        );
+    }
+
+    #[test]
+    fn reserved_register_bits_are_rejected() {
+        let mut bytes = StoredConfig::default().to_bytes();
+        bytes[2 + 2] |= 0b11;
+        let crc = super::crc16(&bytes[..BLOB_LEN - 2]);
+        bytes[BLOB_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            StoredConfig::from_bytes(&bytes),
+            Err(PersistError::InvalidRegister { register: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+//! Reset and power sequencing.
+//!
+//! [`PowerSequencer`] owns the ENABLE and RESET pins and a delay and applies
+//! the settling times between supply, reset, configuration and enable. The
+//! configuration is kept in the [`Tdc1000`] shadow and rewritten whenever the
+//! device lost it. With the `embedded-hal-1` feature an embedded-hal 1.0
+//! `DelayNs` can be used through `DelayNsAdapter`.
+//!
+//! ```
+//! use tdc1000::power::PowerSequencer;
+//! use tdc1000::simulator::Simulator;
+//! use tdc1000::Tdc1000;
+//! # struct Delay;
+//! # impl embedded_hal::blocking::delay::DelayUs<u32> for Delay {
+//! #     fn delay_us(&mut self, _us: u32) {}
+//! # }
+//!
+//! let simulator = Simulator::new();
+//! let mut tdc1000 = Tdc1000::default();
+//! let mut power = PowerSequencer::new(
+//!     simulator.enable_pin(),
+//!     simulator.reset_pin(),
+//!     Delay,
+//! );
+//! power
+//!     .power_up(&mut tdc1000, &mut simulator.cs(), &mut simulator.spi())
+//!     .unwrap();
+//! assert!(simulator.is_enabled());
+//! ```
+
+pub mod estimate;
+
+use crate::trace::SpiTrace;
+use crate::{Error, Pin, Tdc1000};
+use hal::{
+    blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+
+/// Conservative RESET high time and the time until the device accepts SPI
+/// accesses again.
+pub(crate) const RESET_PULSE_US: u32 = 10;
+pub(crate) const RESET_RECOVERY_US: u32 = 100;
+
+/// Default time for the supply and the internal references to settle after
+/// power up.
+const POWER_UP_SETTLING_US: u32 = 10_000;
+/// Default time for the common mode reference to settle after ENABLE rises.
+const ENABLE_SETTLING_US: u32 = 1_000;
+
+/// Error of the ENABLE or the RESET pin, which may have different types.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerPinError<EnE, RstE> {
+    Enable(EnE),
+    Reset(RstE),
+}
+
+/// Pin error type of a [`PowerSequencer`] with the pins `EN` and `RST`.
+pub type PinError<EN, RST> =
+    PowerPinError<<EN as OutputPin>::Error, <RST as OutputPin>::Error>;
+
+/// Uses an embedded-hal 1.0 `DelayNs` where a `DelayUs` is expected.
+#[cfg(feature = "embedded-hal-1")]
+pub struct DelayNsAdapter<D>(pub D);
+
+#[cfg(feature = "embedded-hal-1")]
+impl<D: embedded_hal_1::delay::DelayNs> DelayUs<u32> for DelayNsAdapter<D> {
+    fn delay_us(&mut self, us: u32) {
+        self.0.delay_us(us);
+    }
+}
+
+pub struct PowerSequencer<EN, RST, D> {
+    enable: EN,
+    reset: RST,
+    delay: D,
+    power_up_settling_us: u32,
+    enable_settling_us: u32,
+}
+
+impl<EN, RST, D> PowerSequencer<EN, RST, D>
+where
+    EN: OutputPin,
+    RST: OutputPin,
+    D: DelayUs<u32>,
+{
+    pub fn new(enable: EN, reset: RST, delay: D) -> Self {
+        PowerSequencer {
+            enable,
+            reset,
+            delay,
+            power_up_settling_us: POWER_UP_SETTLING_US,
+            enable_settling_us: ENABLE_SETTLING_US,
+        }
+    }
+
+    /// Overrides the settling times, e.g. for a larger VCOM capacitor.
+    pub fn set_settling_times(&mut self, power_up_us: u32, enable_us: u32) {
+        self.power_up_settling_us = power_up_us;
+        self.enable_settling_us = enable_us;
+    }
+
+    pub fn release(self) -> (EN, RST, D) {
+        (self.enable, self.reset, self.delay)
+    }
+
+    /// Waits for the supply to settle, resets the device, writes the
+    /// configuration and enables the device.
+    pub fn power_up<T, CS, SPI, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+    ) -> Result<(), Error<CsE, SpiE, PinError<EN, RST>>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        self.sleep()
+            .map_err(|error| Error::PinError(Pin::Enable, error))?;
+        self.reset.set_low().map_err(reset_error)?;
+        self.delay.delay_us(self.power_up_settling_us);
+        self.hard_reset(tdc1000, cs, spi)?;
+        self.wake(tdc1000, cs, spi)
+    }
+
+    /// Pulses RESET and rewrites the whole configuration. ENABLE is left as
+    /// it is.
+    pub fn hard_reset<T, CS, SPI, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+    ) -> Result<(), Error<CsE, SpiE, PinError<EN, RST>>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        self.reset.set_high().map_err(reset_error)?;
+        self.delay.delay_us(RESET_PULSE_US);
+        self.reset.set_low().map_err(reset_error)?;
+        self.delay.delay_us(RESET_RECOVERY_US);
+        tdc1000.mark_all_dirty();
+        tdc1000.write_changes(cs, spi).map_err(Error::widen)
+    }
+
+    /// Pulls ENABLE low. The registers keep their values.
+    pub fn sleep(&mut self) -> Result<(), PinError<EN, RST>> {
+        self.enable.set_low().map_err(PowerPinError::Enable)
+    }
+
+    /// Writes configuration changes made while sleeping, raises ENABLE and
+    /// waits for the device to settle.
+    pub fn wake<T, CS, SPI, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+    ) -> Result<(), Error<CsE, SpiE, PinError<EN, RST>>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        tdc1000.write_changes(cs, spi).map_err(Error::widen)?;
+        self.enable.set_high().map_err(enable_error)?;
+        self.delay.delay_us(self.enable_settling_us);
+        Ok(())
+    }
+}
+
+fn enable_error<CsE, SpiE, EnE, RstE>(
+    error: EnE,
+) -> Error<CsE, SpiE, PowerPinError<EnE, RstE>> {
+    Error::PinError(Pin::Enable, PowerPinError::Enable(error))
+}
+
+fn reset_error<CsE, SpiE, EnE, RstE>(
+    error: RstE,
+) -> Error<CsE, SpiE, PowerPinError<EnE, RstE>> {
+    Error::PinError(Pin::Reset, PowerPinError::Reset(error))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::power::PowerSequencer;
+    use crate::simulator::Simulator;
+    use crate::{PgaGain, Tdc1000};
+    use core::cell::Cell;
+    use hal::blocking::delay::DelayUs;
+
+    struct CountingDelay<'a>(&'a Cell<u32>);
+
+    impl DelayUs<u32> for CountingDelay<'_> {
+        fn delay_us(&mut self, us: u32) {
+            self.0.set(self.0.get() + us);
+        }
+    }
+
+    #[test]
+    fn power_up_configures_and_enables() {
+        let simulator = Simulator::new();
+        simulator.set_register(0, 0);
+        let waited = Cell::new(0);
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB15);
+        let mut power = PowerSequencer::new(
+            simulator.enable_pin(),
+            simulator.reset_pin(),
+            CountingDelay(&waited),
+        );
+        power
+            .power_up(&mut tdc1000, &mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert!(simulator.is_enabled());
+        assert_eq!(simulator.register(0), tdc1000.get_config_0_value());
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+        assert_eq!(waited.get(), 10_000 + 10 + 100 + 1_000);
+    }
+
+    #[test]
+    fn changes_while_sleeping_are_written_on_wake() {
+        let simulator = Simulator::new();
+        let waited = Cell::new(0);
+        let mut tdc1000 = Tdc1000::default();
+        let mut power = PowerSequencer::new(
+            simulator.enable_pin(),
+            simulator.reset_pin(),
+            CountingDelay(&waited),
+        );
+        power
+            .power_up(&mut tdc1000, &mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        power.sleep().unwrap();
+        assert!(!simulator.is_enabled());
+
+        tdc1000.set_pga_gain(PgaGain::DB21);
+        simulator.clear_log();
+        power
+            .wake(&mut tdc1000, &mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert!(simulator.is_enabled());
+        assert_eq!(simulator.transaction_count(), 1);
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+    }
+
+    #[cfg(feature = "embedded-hal-1")]
+    #[test]
+    fn delay_ns_is_adapted() {
+        use crate::power::DelayNsAdapter;
+
+        struct NsDelay<'a>(&'a Cell<u32>);
+
+        impl embedded_hal_1::delay::DelayNs for NsDelay<'_> {
+            fn delay_ns(&mut self, ns: u32) {
+                self.0.set(self.0.get() + ns);
+            }
+        }
+
+        let waited = Cell::new(0);
+        DelayNsAdapter(NsDelay(&waited)).delay_us(10);
+        assert_eq!(waited.get(), 10_000);
+    }
+
+    #[test]
+    fn hard_reset_restores_the_configuration() {
+        let simulator = Simulator::new();
+        let waited = Cell::new(0);
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB6);
+        tdc1000
+            .write_changes(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.set_register(5, 0);
+        let mut power = PowerSequencer::new(
+            simulator.enable_pin(),
+            simulator.reset_pin(),
+            CountingDelay(&waited),
+        );
+        power
+            .hard_reset(&mut tdc1000, &mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+        assert!(!tdc1000.has_changes());
+    }
+}
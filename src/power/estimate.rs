@@ -0,0 +1,200 @@
+//! Supply current and battery life estimation.
+//!
+//! [`estimate`] models one measurement as the wake up settling time followed
+//! by the configured number of measurement cycles. Each cycle transmits the
+//! TX burst and listens for the TOF timeout window, which makes the result
+//! an upper bound for measurements whose echo arrives earlier. LNA and PGA
+//! draw current while listening unless bypassed, and also while
+//! transmitting unless power blanking is enabled.
+
+use crate::config::Tdc1000Config;
+use crate::{AmplifierControl, PowerBlanking};
+
+/// Supply currents in A.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SupplyCurrents {
+    /// ENABLE low.
+    pub standby: f32,
+    /// ENABLE high, references and clock running.
+    pub active: f32,
+    /// Additional current of the TX drivers while transmitting.
+    pub transmit: f32,
+    pub lna: f32,
+    pub pga: f32,
+}
+
+/// Approximate typical figures at 3.3 V. The TX current depends on the
+/// transducer load, take the values of the board at hand for accurate
+/// results.
+impl Default for SupplyCurrents {
+    fn default() -> Self {
+        SupplyCurrents {
+            standby: 0.5e-6,
+            active: 0.3e-3,
+            transmit: 2.0e-3,
+            lna: 0.8e-3,
+            pga: 1.5e-3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowerEstimate {
+    /// Charge drawn by one measurement in C.
+    pub charge_per_measurement: f32,
+    /// Time the device is enabled per measurement in s.
+    pub active_time: f32,
+    /// Average supply current in A including standby.
+    pub average_current: f32,
+}
+
+impl PowerEstimate {
+    /// Battery life in hours for a battery of `capacity_mah`, ignoring self
+    /// discharge and the rest of the system.
+    pub fn battery_life_hours(&self, capacity_mah: f32) -> f32 {
+        capacity_mah * 1e-3 / self.average_current
+    }
+}
+
+/// Estimates the supply current of `config` with a CLKIN of `clkin` Hz,
+/// `rate` measurements per second and `settling` s between enabling the
+/// device and the first TX burst.
+pub fn estimate(
+    config: &Tdc1000Config,
+    clkin: f32,
+    rate: f32,
+    settling: f32,
+    currents: &SupplyCurrents,
+) -> PowerEstimate {
+    let tx_divider = 2 << config.config0.tx_frequency_divider as u32;
+    let tx_time =
+        config.config0.tx_pulses.get_value() as f32 * tx_divider as f32 / clkin;
+    let t0 = (1 << config.clock_rate.clock_in_div as u32) as f32 / clkin;
+    let receive_time =
+        t0 * (128 << config.timeout.tof_timeout_crl as u32) as f32;
+    let amplifier_time = match config.config3.blanking {
+        PowerBlanking::EnablePowerBlanking => receive_time,
+        PowerBlanking::DisablePowerBlanking => tx_time + receive_time,
+    };
+    let amplifier = &config.amplifier_and_time_of_flight;
+    let mut amplifier_current = 0.0;
+    if amplifier.lna_ctrl == AmplifierControl::Active {
+        amplifier_current += currents.lna;
+    }
+    if amplifier.pga_ctrl == AmplifierControl::Active {
+        amplifier_current += currents.pga;
+    }
+
+    let cycles = (1 << config.config1.measurement_cycles as u32) as f32;
+    let cycle_charge = currents.active * (tx_time + receive_time)
+        + currents.transmit * tx_time
+        + amplifier_current * amplifier_time;
+    let charge_per_measurement =
+        currents.active * settling + cycles * cycle_charge;
+    let active_time = settling + cycles * (tx_time + receive_time);
+    let standby_share = (1.0 - rate * active_time).max(0.0);
+    PowerEstimate {
+        charge_per_measurement,
+        active_time,
+        average_current: rate * charge_per_measurement
+            + currents.standby * standby_share,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::config::Tdc1000Config;
+    use crate::power::estimate::{estimate, PowerEstimate, SupplyCurrents};
+    use crate::{
+        AmplifierControl, MeasurementCycles, PowerBlanking, TofTimeoutControl,
+        TxFrequencyDivider, TxPulses,
+    };
+
+    const CURRENTS: SupplyCurrents = SupplyCurrents {
+        standby: 0.0,
+        active: 1e-3,
+        transmit: 2e-3,
+        lna: 1e-3,
+        pga: 1e-3,
+    };
+
+    fn config() -> Tdc1000Config {
+        Tdc1000Config::new()
+            .tx_divider(TxFrequencyDivider::DivideBy8)
+            .pulses(TxPulses::new_const(10))
+            .measurement_cycles(MeasurementCycles::MeasurementCycles1)
+            .tof_timeout_ctrl(TofTimeoutControl::T0Times128)
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected * 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn charge_follows_the_configuration() {
+        // 10 µs TX at 1 MHz and a 16 µs receive window at 8 MHz.
+        let single = estimate(&config(), 8e6, 1.0, 0.0, &CURRENTS);
+        assert_close(single.active_time, 26e-6);
+        assert_close(single.charge_per_measurement, 26e-9 + 20e-9 + 52e-9);
+        assert_close(single.average_current, 98e-9);
+
+        let blanked = estimate(
+            &config().blanking(PowerBlanking::EnablePowerBlanking),
+            8e6,
+            1.0,
+            0.0,
+            &CURRENTS,
+        );
+        assert_close(blanked.charge_per_measurement, 26e-9 + 20e-9 + 32e-9);
+
+        let bypassed = estimate(
+            &config()
+                .lna_control(AmplifierControl::BypassedAndPoweredOff)
+                .pga_control(AmplifierControl::BypassedAndPoweredOff),
+            8e6,
+            1.0,
+            0.0,
+            &CURRENTS,
+        );
+        assert_close(bypassed.charge_per_measurement, 26e-9 + 20e-9);
+
+        let averaged = estimate(
+            &config().measurement_cycles(MeasurementCycles::MeasurementCycles8),
+            8e6,
+            1.0,
+            1e-3,
+            &CURRENTS,
+        );
+        assert_close(averaged.charge_per_measurement, 1e-6 + 8.0 * 98e-9);
+    }
+
+    #[test]
+    fn standby_current_and_battery_life() {
+        let currents = SupplyCurrents {
+            standby: 1e-6,
+            ..CURRENTS
+        };
+        let ten_per_second = estimate(&config(), 8e6, 10.0, 0.0, &currents);
+        assert_close(
+            ten_per_second.average_current,
+            980e-9 + 1e-6 * (1.0 - 260e-6),
+        );
+
+        let one_milliamp = PowerEstimate {
+            charge_per_measurement: 0.0,
+            active_time: 0.0,
+            average_current: 1e-3,
+        };
+        assert_close(one_milliamp.battery_life_hours(1000.0), 1000.0);
+    }
+}
@@ -0,0 +1,270 @@
+//! Chip presence detection and self-test.
+//!
+//! Without a device on the bus every read returns whatever level MISO floats
+//! to. [`Tdc1000::probe`] writes test patterns to writable registers and reads
+//! them back, pulses RESET and checks the reset values, and finally restores
+//! the configuration held by the [`Tdc1000`] shadow.
+
+use crate::power::{RESET_PULSE_US, RESET_RECOVERY_US};
+use crate::trace::SpiTrace;
+use crate::{
+    ConfigAddresses, Error, Pin, Tdc1000, CONFIG_REGISTERS, RESET_VALUES,
+    SPI_WRITE_BIT,
+};
+use hal::{
+    blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+
+const TEST_PATTERNS: [u8; 2] = [0x55, 0xaa];
+
+/// Registers used for the pattern test and their implemented bits.
+const TEST_REGISTERS: [(ConfigAddresses, u8); 3] = [
+    (ConfigAddresses::Config0, 0xff),
+    (ConfigAddresses::Config3, 0x7f),
+    (ConfigAddresses::Tof0, 0xff),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProbeResult {
+    Ok,
+    /// All reads returned the same all zero or all one value regardless of
+    /// the written pattern.
+    NotPresent,
+    /// Bits of `register` that read back as one although zero was written,
+    /// or as zero although one was written.
+    StuckBits {
+        register: u8,
+        stuck_high: u8,
+        stuck_low: u8,
+    },
+    /// `register` did not hold its reset value after the RESET pulse.
+    ResetDefaultsMismatch {
+        register: u8,
+        expected: u8,
+        actual: u8,
+    },
+}
+
+impl<T: SpiTrace> Tdc1000<T> {
+    /// Runs the self-test. The device is reset in the process and the
+    /// configuration of `self` is written afterwards, unless an SPI, chip
+    /// select or RESET pin error aborts the probe.
+    pub fn probe<CS, SPI, RST, D, CsE, SpiE, PinE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+        reset: &mut RST,
+        delay: &mut D,
+    ) -> Result<ProbeResult, Error<CsE, SpiE, PinE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        RST: OutputPin<Error = PinE>,
+        D: DelayUs<u32>,
+    {
+        let result = self.run_probe(cs, spi, reset, delay);
+        // The test patterns and the reset overwrote the device registers.
+        self.mark_all_dirty();
+        let result = result?;
+        self.write_changes(cs, spi).map_err(Error::widen)?;
+        Ok(result)
+    }
+
+    fn run_probe<CS, SPI, RST, D, CsE, SpiE, PinE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+        reset: &mut RST,
+        delay: &mut D,
+    ) -> Result<ProbeResult, Error<CsE, SpiE, PinE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        RST: OutputPin<Error = PinE>,
+        D: DelayUs<u32>,
+    {
+        let mut first_read = None;
+        let mut constant_bus = true;
+        let mut stuck = None;
+        for (address, mask) in TEST_REGISTERS.iter() {
+            let mut stuck_high = 0;
+            let mut stuck_low = 0;
+            for pattern in TEST_PATTERNS.iter() {
+                let written = pattern & mask;
+                self.write_to_spi(
+                    cs,
+                    spi,
+                    [*address as u8 | SPI_WRITE_BIT, written],
+                )
+                .map_err(Error::widen)?;
+                let read = self
+                    .read_from_spi(cs, spi, *address as u8)
+                    .map_err(Error::widen)?;
+                let first = *first_read.get_or_insert(read);
+                constant_bus &= read == first && (read == 0 || read == 0xff);
+                stuck_high |= read & !written & mask;
+                stuck_low |= !read & written & mask;
+            }
+            if stuck.is_none() && (stuck_high != 0 || stuck_low != 0) {
+                stuck = Some(ProbeResult::StuckBits {
+                    register: *address as u8,
+                    stuck_high,
+                    stuck_low,
+                });
+            }
+        }
+        if constant_bus {
+            return Ok(ProbeResult::NotPresent);
+        }
+        if let Some(stuck) = stuck {
+            return Ok(stuck);
+        }
+
+        let pin_error = |error| Error::PinError(Pin::Reset, error);
+        reset.set_high().map_err(pin_error)?;
+        delay.delay_us(RESET_PULSE_US);
+        reset.set_low().map_err(pin_error)?;
+        delay.delay_us(RESET_RECOVERY_US);
+        for address in CONFIG_REGISTERS.iter() {
+            let expected = RESET_VALUES[*address as usize];
+            let actual = self
+                .read_from_spi(cs, spi, *address as u8)
+                .map_err(Error::widen)?;
+            if actual != expected {
+                return Ok(ProbeResult::ResetDefaultsMismatch {
+                    register: *address as u8,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(ProbeResult::Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::probe::ProbeResult;
+    use crate::simulator::{SimulatedSpi, Simulator, SimulatorError};
+    use crate::{PgaGain, Tdc1000};
+    use hal::blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    };
+
+    struct NoDelay;
+
+    impl DelayUs<u32> for NoDelay {
+        fn delay_us(&mut self, _us: u32) {}
+    }
+
+    /// MISO pulled up, no device answering.
+    struct FloatingSpi;
+
+    impl Transfer<u8> for FloatingSpi {
+        type Error = SimulatorError;
+
+        fn transfer<'w>(
+            &mut self,
+            words: &'w mut [u8],
+        ) -> Result<&'w [u8], SimulatorError> {
+            words[1] = 0xff;
+            Ok(words)
+        }
+    }
+
+    impl Write<u8> for FloatingSpi {
+        type Error = SimulatorError;
+
+        fn write(&mut self, _words: &[u8]) -> Result<(), SimulatorError> {
+            Ok(())
+        }
+    }
+
+    /// Reads bit 4 of CONFIG_3 as one.
+    struct StuckSpi<'a>(SimulatedSpi<'a>);
+
+    impl Transfer<u8> for StuckSpi<'_> {
+        type Error = SimulatorError;
+
+        fn transfer<'w>(
+            &mut self,
+            words: &'w mut [u8],
+        ) -> Result<&'w [u8], SimulatorError> {
+            self.0.transfer(words)?;
+            if words[0] == 3 {
+                words[1] |= 0x10;
+            }
+            Ok(words)
+        }
+    }
+
+    impl Write<u8> for StuckSpi<'_> {
+        type Error = SimulatorError;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), SimulatorError> {
+            self.0.write(words)
+        }
+    }
+
+    #[test]
+    fn present_device_passes_and_is_restored() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB12);
+        let result = tdc1000
+            .probe(
+                &mut simulator.cs(),
+                &mut simulator.spi(),
+                &mut simulator.reset_pin(),
+                &mut NoDelay,
+            )
+            .unwrap();
+        assert_eq!(result, ProbeResult::Ok);
+        assert_eq!(simulator.register(0), tdc1000.get_config_0_value());
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+        assert!(!tdc1000.has_changes());
+    }
+
+    #[test]
+    fn floating_bus_is_not_present() {
+        let simulator = Simulator::new();
+        let result = Tdc1000::default()
+            .probe(
+                &mut simulator.cs(),
+                &mut FloatingSpi,
+                &mut simulator.reset_pin(),
+                &mut NoDelay,
+            )
+            .unwrap();
+        assert_eq!(result, ProbeResult::NotPresent);
+    }
+
+    #[test]
+    fn stuck_bits_are_reported() {
+        let simulator = Simulator::new();
+        let result = Tdc1000::default()
+            .probe(
+                &mut simulator.cs(),
+                &mut StuckSpi(simulator.spi()),
+                &mut simulator.reset_pin(),
+                &mut NoDelay,
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            ProbeResult::StuckBits {
+                register: 3,
+                stuck_high: 0x10,
+                stuck_low: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Per-channel settings for transducer pairs that need different gain or
+//! threshold.
+//!
+//! With profiles set, [`Tdc1000::set_active_channel`] also applies the
+//! profile of the selected channel. Only registers whose value changes are
+//! marked dirty, so switching channels writes as few registers as possible.
+//! Changing the PGA gain, the echo qualification threshold or the number of
+//! TX pulses updates the profile of the active channel as well, so settings
+//! tuned by hand, by [`crate::agc::AgcController`] or by
+//! [`crate::sweep::SetupWizard`] survive switching channels.
+//!
+//! Profiles need the driver to know which channel is active. With channel
+//! swap enabled the device alternates the channel on every measurement, so
+//! profiles are not applied and [`Tdc1000::measure_on`] and
+//! [`crate::device::Device::select_channel`] fail with
+//! [`Error::InvalidConfiguration`]. With the external channel select enabled
+//! CONFIG_2 does not select the channel, use
+//! [`crate::device::Device::select_channel`], which applies the profiles
+//! while driving the CHSEL pin.
+
+use crate::trace::SpiTrace;
+use crate::{
+    ChannelSelect, ChannelSwap, EchoQualificationThreshold, Error,
+    ExternalChannelSelect, PgaGain, Tdc1000, TxPulses,
+};
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelProfile {
+    pub pga_gain: PgaGain,
+    pub echo_qualification_threshold: EchoQualificationThreshold,
+    pub tx_pulses: TxPulses,
+}
+
+impl<T: SpiTrace> Tdc1000<T> {
+    /// Profile with the current settings of `self`.
+    pub fn current_channel_profile(&self) -> ChannelProfile {
+        ChannelProfile {
+            pga_gain: self.amplifier_and_time_of_flight.pga_gain,
+            echo_qualification_threshold: self
+                .config3
+                .echo_qualification_threshold,
+            tx_pulses: self.config0.tx_pulses,
+        }
+    }
+
+    /// Uses `channel1` and `channel2` from now on and applies the profile of
+    /// the active channel unless channel swap is enabled. Later changes of
+    /// the profile settings are stored in the profile of the active channel.
+    pub fn set_channel_profiles(
+        &mut self,
+        channel1: ChannelProfile,
+        channel2: ChannelProfile,
+    ) {
+        self.profiles = Some([channel1, channel2]);
+        self.apply_channel_profile(self.config2.channel_select);
+    }
+
+    /// Stops switching settings with the channel. The current settings are
+    /// kept.
+    pub fn clear_channel_profiles(&mut self) {
+        self.profiles = None;
+    }
+
+    pub fn channel_profile(
+        &self,
+        channel: ChannelSelect,
+    ) -> Option<&ChannelProfile> {
+        self.profiles
+            .as_ref()
+            .map(|profiles| &profiles[channel as usize])
+    }
+
+    /// Selects `channel` and writes the registers that changed.
+    ///
+    /// Fails with [`Error::InvalidConfiguration`] if profiles are set and
+    /// channel swap or the external channel select is enabled.
+    pub fn measure_on<CS, SPI, CsE, SpiE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+        channel: ChannelSelect,
+    ) -> Result<(), Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        let external = self.config2.ext_channel_select
+            == ExternalChannelSelect::EnableExternalChannelSelect;
+        if self.profiles_conflict() || self.profiles.is_some() && external {
+            return Err(Error::InvalidConfiguration);
+        }
+        self.set_active_channel(channel);
+        self.write_changes(cs, spi)
+    }
+
+    /// Whether profiles are set although channel swap is enabled.
+    pub(crate) fn profiles_conflict(&self) -> bool {
+        self.profiles.is_some()
+            && self.config2.channel_swap == ChannelSwap::EnableSwap
+    }
+
+    /// Profile that follows the settings, `None` without profiles or with
+    /// channel swap enabled.
+    pub(crate) fn active_profile_mut(&mut self) -> Option<&mut ChannelProfile> {
+        if self.config2.channel_swap == ChannelSwap::EnableSwap {
+            return None;
+        }
+        let channel = self.config2.channel_select as usize;
+        self.profiles
+            .as_mut()
+            .map(|profiles| &mut profiles[channel])
+    }
+
+    pub(crate) fn apply_channel_profile(&mut self, channel: ChannelSelect) {
+        if self.config2.channel_swap == ChannelSwap::DisableSwap {
+            self.apply_profile_settings(channel);
+        }
+    }
+
+    /// Applies the settings of the profile of `channel` without touching
+    /// the channel selection.
+    pub(crate) fn apply_profile_settings(&mut self, channel: ChannelSelect) {
+        let profile = match self.profiles {
+            Some(profiles) => profiles[channel as usize],
+            None => return,
+        };
+        if self.amplifier_and_time_of_flight.pga_gain != profile.pga_gain {
+            self.set_pga_gain(profile.pga_gain);
+        }
+        if self.config3.echo_qualification_threshold
+            != profile.echo_qualification_threshold
+        {
+            self.set_echo_qualification_threshold(
+                profile.echo_qualification_threshold,
+            );
+        }
+        if self.config0.tx_pulses != profile.tx_pulses {
+            self.set_number_of_tx_pulses(profile.tx_pulses);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::profile::ChannelProfile;
+    use crate::simulator::{Simulator, Transaction};
+    use crate::{
+        ChannelSelect, ChannelSwap, EchoQualificationThreshold, Error,
+        ExternalChannelSelect, PgaGain, Tdc1000, TxPulses,
+    };
+
+    fn written_addresses(simulator: &Simulator) -> std::vec::Vec<u8> {
+        (0..simulator.transaction_count())
+            .filter_map(|index| match simulator.transaction(index) {
+                Some(Transaction::Write { address, .. }) => Some(address),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn profiles() -> (ChannelProfile, ChannelProfile) {
+        let channel1 = ChannelProfile {
+            pga_gain: PgaGain::DB9,
+            ..ChannelProfile::default()
+        };
+        let channel2 = ChannelProfile {
+            pga_gain: PgaGain::DB15,
+            echo_qualification_threshold: EchoQualificationThreshold::Mv220,
+            tx_pulses: TxPulses::new_const(10),
+        };
+        (channel1, channel2)
+    }
+
+    #[test]
+    fn switching_channels_writes_only_differing_registers() {
+        let simulator = Simulator::new();
+        let mut cs = simulator.cs();
+        let mut spi = simulator.spi();
+        let mut tdc1000 = Tdc1000::default();
+        let (channel1, channel2) = profiles();
+        tdc1000.set_channel_profiles(channel1, channel2);
+        tdc1000.write_changes(&mut cs, &mut spi).unwrap();
+        assert_eq!(tdc1000.current_channel_profile(), channel1);
+
+        simulator.clear_log();
+        tdc1000
+            .measure_on(&mut cs, &mut spi, ChannelSelect::Channel2)
+            .unwrap();
+        assert_eq!(tdc1000.current_channel_profile(), channel2);
+        // CONFIG_0 (pulses), CONFIG_2 (channel), CONFIG_3 (threshold) and
+        // TOF_1 (gain), TOF_0 is unchanged.
+        assert_eq!(written_addresses(&simulator), [0, 2, 3, 5]);
+        assert_eq!(simulator.register(2), tdc1000.get_config_2_value());
+
+        simulator.clear_log();
+        tdc1000
+            .measure_on(&mut cs, &mut spi, ChannelSelect::Channel2)
+            .unwrap();
+        assert!(written_addresses(&simulator).is_empty());
+    }
+
+    #[test]
+    fn tuned_settings_survive_switching_channels() {
+        let simulator = Simulator::new();
+        let mut cs = simulator.cs();
+        let mut spi = simulator.spi();
+        let mut tdc1000 = Tdc1000::default();
+        let (channel1, channel2) = profiles();
+        tdc1000.set_channel_profiles(channel1, channel2);
+        tdc1000
+            .measure_on(&mut cs, &mut spi, ChannelSelect::Channel2)
+            .unwrap();
+        tdc1000.set_pga_gain(PgaGain::DB21);
+        tdc1000.set_echo_qualification_threshold(
+            EchoQualificationThreshold::Mv410,
+        );
+
+        tdc1000
+            .measure_on(&mut cs, &mut spi, ChannelSelect::Channel1)
+            .unwrap();
+        assert_eq!(tdc1000.current_channel_profile(), channel1);
+        tdc1000
+            .measure_on(&mut cs, &mut spi, ChannelSelect::Channel2)
+            .unwrap();
+        let tuned = ChannelProfile {
+            pga_gain: PgaGain::DB21,
+            echo_qualification_threshold: EchoQualificationThreshold::Mv410,
+            ..channel2
+        };
+        assert_eq!(tdc1000.current_channel_profile(), tuned);
+        assert_eq!(
+            tdc1000.channel_profile(ChannelSelect::Channel2),
+            Some(&tuned)
+        );
+        assert_eq!(
+            tdc1000.channel_profile(ChannelSelect::Channel1),
+            Some(&channel1)
+        );
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+    }
+
+    #[test]
+    fn channel_swap_is_kept_and_conflicts_with_profiles() {
+        let simulator = Simulator::new();
+        let mut cs = simulator.cs();
+        let mut spi = simulator.spi();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_channel_swap(ChannelSwap::EnableSwap);
+        let (channel1, channel2) = profiles();
+        tdc1000.set_channel_profiles(channel1, channel2);
+        tdc1000.set_active_channel(ChannelSelect::Channel2);
+        assert_eq!(tdc1000.config2.channel_swap, ChannelSwap::EnableSwap);
+        assert_eq!(
+            tdc1000.current_channel_profile(),
+            ChannelProfile::default()
+        );
+        assert_eq!(
+            tdc1000.channel_profile(ChannelSelect::Channel1),
+            Some(&channel1)
+        );
+        assert_eq!(
+            tdc1000.measure_on(&mut cs, &mut spi, ChannelSelect::Channel1),
+            Err(Error::InvalidConfiguration)
+        );
+
+        tdc1000.set_channel_swap(ChannelSwap::DisableSwap);
+        tdc1000.set_external_channel_select(
+            ExternalChannelSelect::EnableExternalChannelSelect,
+        );
+        assert_eq!(
+            tdc1000.measure_on(&mut cs, &mut spi, ChannelSelect::Channel1),
+            Err(Error::InvalidConfiguration)
+        );
+
+        tdc1000.clear_channel_profiles();
+        tdc1000.set_active_channel(ChannelSelect::Channel1);
+        assert_eq!(
+            tdc1000.current_channel_profile(),
+            ChannelProfile::default()
+        );
+    }
+}
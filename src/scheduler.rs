@@ -0,0 +1,298 @@
+//! Periodic measurements with the front end disabled in between.
+//!
+//! Each cycle of [`DutyCycleScheduler`] wakes the device, writes pending
+//! configuration changes, triggers and captures one measurement and puts the
+//! device back to sleep. The rest of the period is spent in an idle hook,
+//! typically a low power delay that stops the MCU until an RTC alarm.
+
+use crate::measurement::{MeasureError, TofCapture, TofMeasurement};
+use crate::power::{PinError, PowerSequencer};
+use crate::trace::SpiTrace;
+use crate::{Error, Pin, Tdc1000};
+use hal::{
+    blocking::{
+        delay::DelayUs,
+        spi::{Transfer, Write},
+    },
+    digital::v2::OutputPin,
+};
+
+/// Free running microsecond counter, wrapping around at `u32::MAX`.
+pub trait Clock {
+    fn now_us(&mut self) -> u32;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DutyCycleStatistics {
+    pub cycles: u32,
+    /// Cycles whose active time exceeded the period.
+    pub overruns: u32,
+    /// Time the device was enabled or configured.
+    pub active_us: u64,
+    pub elapsed_us: u64,
+}
+
+impl DutyCycleStatistics {
+    /// Share of the elapsed time the device was active.
+    pub fn duty_cycle(&self) -> f32 {
+        if self.elapsed_us == 0 {
+            0.0
+        } else {
+            self.active_us as f32 / self.elapsed_us as f32
+        }
+    }
+}
+
+/// Error of [`DutyCycleScheduler::run_once`].
+pub type CycleError<CsE, SpiE, CapE, EN, RST> =
+    MeasureError<CsE, SpiE, CapE, PinError<EN, RST>>;
+
+pub struct DutyCycleScheduler<EN, RST, D, CLK> {
+    power: PowerSequencer<EN, RST, D>,
+    clock: CLK,
+    period_us: u32,
+    /// Cycles between rewrites of the whole configuration, 0 to write only
+    /// changes.
+    refresh_interval: u16,
+    cycles_since_refresh: u16,
+    statistics: DutyCycleStatistics,
+}
+
+impl<EN, RST, D, CLK> DutyCycleScheduler<EN, RST, D, CLK>
+where
+    EN: OutputPin,
+    RST: OutputPin,
+    D: DelayUs<u32>,
+    CLK: Clock,
+{
+    /// Measures every `period_us`. The device should be powered up already.
+    pub fn new(
+        power: PowerSequencer<EN, RST, D>,
+        clock: CLK,
+        period_us: u32,
+    ) -> Self {
+        DutyCycleScheduler {
+            power,
+            clock,
+            period_us,
+            refresh_interval: 0,
+            cycles_since_refresh: 0,
+            statistics: DutyCycleStatistics::default(),
+        }
+    }
+
+    /// Rewrites all registers every `cycles` cycles to recover from lost
+    /// register contents, 0 disables the refresh.
+    pub fn set_refresh_interval(&mut self, cycles: u16) {
+        self.refresh_interval = cycles;
+    }
+
+    pub fn statistics(&self) -> &DutyCycleStatistics {
+        &self.statistics
+    }
+
+    pub fn release(self) -> (PowerSequencer<EN, RST, D>, CLK) {
+        (self.power, self.clock)
+    }
+
+    /// Runs one cycle: wake, measure, sleep and idle for the rest of the
+    /// period in `idle`. The device is put to sleep and the period is kept
+    /// even if the measurement failed, so failing cycles do not run back to
+    /// back.
+    pub fn run_once<T, CS, SPI, C, S, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+        idle: &mut S,
+    ) -> Result<TofMeasurement, CycleError<CsE, SpiE, C::Error, EN, RST>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+        S: DelayUs<u32>,
+    {
+        let start = self.clock.now_us();
+        if self.refresh_interval > 0 {
+            self.cycles_since_refresh += 1;
+            if self.cycles_since_refresh >= self.refresh_interval {
+                self.cycles_since_refresh = 0;
+                tdc1000.mark_all_dirty();
+            }
+        }
+        let result = match self.power.wake(tdc1000, cs, spi) {
+            Ok(()) => tdc1000
+                .measure(cs, spi, capture)
+                .map_err(MeasureError::widen),
+            Err(error) => Err(error.into()),
+        };
+        let slept = self.power.sleep();
+        let active_us = self.clock.now_us().wrapping_sub(start);
+        self.record(active_us);
+        if active_us < self.period_us {
+            idle.delay_us(self.period_us - active_us);
+        }
+        let measurement = result?;
+        slept.map_err(|error| Error::PinError(Pin::Enable, error))?;
+        Ok(measurement)
+    }
+
+    fn record(&mut self, active_us: u32) {
+        let statistics = &mut self.statistics;
+        statistics.cycles = statistics.cycles.saturating_add(1);
+        statistics.active_us += active_us as u64;
+        statistics.elapsed_us += active_us.max(self.period_us) as u64;
+        if active_us > self.period_us {
+            statistics.overruns = statistics.overruns.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::measurement::{MeasureError, TofCapture, TofMeasurement};
+    use crate::power::PowerSequencer;
+    use crate::scheduler::{Clock, DutyCycleScheduler};
+    use crate::simulator::Simulator;
+    use crate::{PgaGain, Tdc1000};
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use hal::blocking::delay::DelayUs;
+
+    /// Advances the shared time instead of waiting.
+    struct FakeDelay<'a>(&'a Cell<u32>);
+
+    impl DelayUs<u32> for FakeDelay<'_> {
+        fn delay_us(&mut self, us: u32) {
+            self.0.set(self.0.get().wrapping_add(us));
+        }
+    }
+
+    struct FakeClock<'a>(&'a Cell<u32>);
+
+    impl Clock for FakeClock<'_> {
+        fn now_us(&mut self) -> u32 {
+            self.0.get()
+        }
+    }
+
+    /// Takes 200 µs and only sees an echo while the device is enabled.
+    struct EnabledCapture<'a>(&'a Simulator, &'a Cell<u32>);
+
+    impl TofCapture for EnabledCapture<'_> {
+        type Error = Infallible;
+
+        fn capture(&mut self) -> Result<TofMeasurement, Infallible> {
+            self.1.set(self.1.get() + 200);
+            Ok(if self.0.is_enabled() {
+                TofMeasurement::new(&[50e-6])
+            } else {
+                TofMeasurement::timeout()
+            })
+        }
+    }
+
+    #[test]
+    fn device_is_enabled_only_while_measuring() {
+        let simulator = Simulator::new();
+        let now = Cell::new(u32::MAX - 100);
+        let power = PowerSequencer::new(
+            simulator.enable_pin(),
+            simulator.reset_pin(),
+            FakeDelay(&now),
+        );
+        let mut scheduler =
+            DutyCycleScheduler::new(power, FakeClock(&now), 1_000_000);
+        let mut tdc1000 = Tdc1000::default();
+        for _ in 0..3 {
+            let measurement = scheduler
+                .run_once(
+                    &mut tdc1000,
+                    &mut simulator.cs(),
+                    &mut simulator.spi(),
+                    &mut EnabledCapture(&simulator, &now),
+                    &mut FakeDelay(&now),
+                )
+                .unwrap();
+            assert_eq!(measurement.first_stop(), Some(50e-6));
+            assert!(!simulator.is_enabled());
+        }
+        let statistics = scheduler.statistics();
+        assert_eq!(statistics.cycles, 3);
+        assert_eq!(statistics.overruns, 0);
+        // 1 ms enable settling and 200 µs capture per second.
+        assert_eq!(statistics.active_us, 3 * 1_200);
+        assert!((statistics.duty_cycle() - 0.0012).abs() < 1e-6);
+        assert_eq!(now.get(), (u32::MAX - 100).wrapping_add(3_000_000));
+    }
+
+    #[test]
+    fn failed_cycles_keep_the_period() {
+        struct BrokenCapture;
+
+        impl TofCapture for BrokenCapture {
+            type Error = ();
+
+            fn capture(&mut self) -> Result<TofMeasurement, ()> {
+                Err(())
+            }
+        }
+
+        let simulator = Simulator::new();
+        let now = Cell::new(0);
+        let power = PowerSequencer::new(
+            simulator.enable_pin(),
+            simulator.reset_pin(),
+            FakeDelay(&now),
+        );
+        let mut scheduler =
+            DutyCycleScheduler::new(power, FakeClock(&now), 1_000_000);
+        let result = scheduler.run_once(
+            &mut Tdc1000::default(),
+            &mut simulator.cs(),
+            &mut simulator.spi(),
+            &mut BrokenCapture,
+            &mut FakeDelay(&now),
+        );
+        assert_eq!(result, Err(MeasureError::CaptureError(())));
+        assert!(!simulator.is_enabled());
+        assert_eq!(now.get(), 1_000_000);
+    }
+
+    #[test]
+    fn configuration_is_refreshed() {
+        let simulator = Simulator::new();
+        let now = Cell::new(0);
+        let power = PowerSequencer::new(
+            simulator.enable_pin(),
+            simulator.reset_pin(),
+            FakeDelay(&now),
+        );
+        let mut scheduler =
+            DutyCycleScheduler::new(power, FakeClock(&now), 1_000);
+        scheduler.set_refresh_interval(2);
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB12);
+        let mut run = |tdc1000: &mut Tdc1000| {
+            simulator.clear_log();
+            scheduler
+                .run_once(
+                    tdc1000,
+                    &mut simulator.cs(),
+                    &mut simulator.spi(),
+                    &mut EnabledCapture(&simulator, &now),
+                    &mut FakeDelay(&now),
+                )
+                .unwrap();
+        };
+        run(&mut tdc1000);
+        simulator.set_register(5, 0);
+        run(&mut tdc1000);
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+    }
+}
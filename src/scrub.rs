@@ -0,0 +1,238 @@
+//! Configuration scrubbing.
+//!
+//! A brown-out or an EMC induced reset silently returns the TDC1000 to its
+//! reset values while the [`Tdc1000`] shadow still holds the intended
+//! configuration. [`Tdc1000::scrub`] reads back all configuration registers,
+//! rewrites the ones that deviate from the shadow and reports what was
+//! corrected. Calling it periodically bounds the time the device runs with a
+//! wrong configuration.
+
+use crate::trace::SpiTrace;
+use crate::{Error, Tdc1000, CONFIG_REGISTERS, RESET_VALUES};
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScrubReport {
+    /// Rewritten registers, one bit per register address.
+    pub corrected: u16,
+    /// Registers deviated and all registers held their reset values, the
+    /// device was most likely reset or power cycled.
+    pub reset_detected: bool,
+}
+
+impl ScrubReport {
+    /// Whether all registers matched the shadow configuration.
+    pub fn is_clean(&self) -> bool {
+        self.corrected == 0
+    }
+
+    pub fn corrected_count(&self) -> u8 {
+        self.corrected.count_ones() as u8
+    }
+
+    pub fn is_corrected(&self, address: u8) -> bool {
+        address < 16 && self.corrected & 1 << address != 0
+    }
+}
+
+/// Totals over all scrubs for telemetry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScrubCounters {
+    pub scrubs: u32,
+    /// Scrubs that had to rewrite at least one register.
+    pub corrections: u32,
+    pub corrected_registers: u32,
+    pub resets_detected: u32,
+}
+
+impl ScrubCounters {
+    pub fn record(&mut self, report: &ScrubReport) {
+        self.scrubs = self.scrubs.saturating_add(1);
+        if !report.is_clean() {
+            self.corrections = self.corrections.saturating_add(1);
+        }
+        self.corrected_registers = self
+            .corrected_registers
+            .saturating_add(report.corrected_count() as u32);
+        if report.reset_detected {
+            self.resets_detected = self.resets_detected.saturating_add(1);
+        }
+    }
+}
+
+impl<T: SpiTrace> Tdc1000<T> {
+    /// Reads all configuration registers and rewrites those that differ from
+    /// the shadow configuration. Rewritten registers are read back, a
+    /// register that still deviates fails with [`Error::VerifyMismatch`].
+    pub fn scrub<CS, SPI, CsE, SpiE>(
+        &mut self,
+        cs: &mut CS,
+        spi: &mut SPI,
+    ) -> Result<ScrubReport, Error<CsE, SpiE>>
+    where
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+    {
+        let mut report = ScrubReport::default();
+        let mut all_reset = true;
+        for address in CONFIG_REGISTERS.iter() {
+            let actual = self.read_from_spi(cs, spi, *address as u8)?;
+            all_reset &= actual == RESET_VALUES[*address as usize];
+            if actual != self.get_register_value(*address) {
+                report.corrected |= 1 << *address as u8;
+                self.write_register(cs, spi, *address)?;
+                let expected = self.get_register_value(*address);
+                let actual = self.read_from_spi(cs, spi, *address as u8)?;
+                if actual != expected {
+                    return Err(Error::VerifyMismatch {
+                        register: *address as u8,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+        report.reset_detected = !report.is_clean() && all_reset;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::scrub::{ScrubCounters, ScrubReport};
+    use crate::simulator::{SimulatedSpi, Simulator, SimulatorError};
+    use crate::{Error, PgaGain, Tdc1000, TxPulses, RESET_VALUES};
+    use hal::blocking::spi::{Transfer, Write};
+
+    /// Reads bit 2 of CONFIG_0 as zero.
+    struct StuckSpi<'a>(SimulatedSpi<'a>);
+
+    impl Transfer<u8> for StuckSpi<'_> {
+        type Error = SimulatorError;
+
+        fn transfer<'w>(
+            &mut self,
+            words: &'w mut [u8],
+        ) -> Result<&'w [u8], SimulatorError> {
+            self.0.transfer(words)?;
+            if words[0] == 0 {
+                words[1] &= !0b100;
+            }
+            Ok(words)
+        }
+    }
+
+    impl Write<u8> for StuckSpi<'_> {
+        type Error = SimulatorError;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), SimulatorError> {
+            self.0.write(words)
+        }
+    }
+
+    fn configured() -> Tdc1000 {
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_number_of_tx_pulses(TxPulses::new_const(12));
+        tdc1000.set_pga_gain(PgaGain::DB18);
+        tdc1000
+    }
+
+    #[test]
+    fn matching_configuration_is_left_alone() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = configured();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.clear_log();
+        let report = tdc1000
+            .scrub(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert!(report.is_clean());
+        assert!(!report.reset_detected);
+        assert_eq!(simulator.transaction_count(), 9);
+    }
+
+    #[test]
+    fn reset_device_is_reconfigured() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = configured();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.reset();
+        let report = tdc1000
+            .scrub(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert!(report.reset_detected);
+        assert!(report.is_corrected(0) && report.is_corrected(5));
+        assert_eq!(simulator.register(0), tdc1000.get_config_0_value());
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+
+        let mut counters = ScrubCounters::default();
+        counters.record(&report);
+        counters.record(&ScrubReport::default());
+        assert_eq!(counters.scrubs, 2);
+        assert_eq!(counters.corrections, 1);
+        assert_eq!(
+            counters.corrected_registers,
+            report.corrected_count() as u32
+        );
+        assert_eq!(counters.resets_detected, 1);
+    }
+
+    #[test]
+    fn single_bit_flip_is_not_a_reset() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = configured();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.set_register(3, simulator.register(3) ^ 0b100);
+        let report = tdc1000
+            .scrub(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_eq!(report.corrected, 1 << 3);
+        assert!(!report.reset_detected);
+        assert_eq!(simulator.register(3), tdc1000.get_config_3_value());
+    }
+
+    #[test]
+    fn single_register_at_reset_value_is_not_a_reset() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = configured();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.set_register(5, RESET_VALUES[5]);
+        let report = tdc1000
+            .scrub(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_eq!(report.corrected, 1 << 5);
+        assert!(!report.reset_detected);
+    }
+
+    #[test]
+    fn failed_rewrite_is_reported() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = configured();
+        let result =
+            tdc1000.scrub(&mut simulator.cs(), &mut StuckSpi(simulator.spi()));
+        assert_eq!(
+            result,
+            Err(Error::VerifyMismatch {
+                register: 0,
+                expected: tdc1000.get_config_0_value(),
+                actual: tdc1000.get_config_0_value() & !0b100,
+            })
+        );
+    }
+}
@@ -0,0 +1,516 @@
+//! Register level TDC1000 simulator for host tests.
+//!
+//! [`Simulator`] hands out an SPI bus, a chip select and ENABLE, RESET, CHSEL
+//! and ERRB pins implementing the same embedded-hal traits the driver uses.
+//! It decodes the address and write bit of every two byte transaction, keeps
+//! the ten registers with their reset values and implements the write one to
+//! clear semantics of ERROR_FLAGS. Every transaction is logged.
+//!
+//! ```
+//! use tdc1000::simulator::Simulator;
+//! use tdc1000::Tdc1000;
+//!
+//! let simulator = Simulator::new();
+//! let mut tdc1000 = Tdc1000::default();
+//! tdc1000
+//!     .write_settings(&mut simulator.cs(), &mut simulator.spi())
+//!     .unwrap();
+//! assert_eq!(simulator.register(0), tdc1000.get_config_0_value());
+//! ```
+
+pub mod acoustic;
+
+pub use crate::RESET_VALUES;
+use crate::{
+    ChannelSelect, ConfigAddresses, ErrorFlagsWrite, CHANNEL_SELECT_BIT_OFFSET,
+    EXTERNAL_CHANNEL_SELECT_BIT_OFFSET, SPI_WRITE_BIT,
+};
+use core::cell::RefCell;
+use core::convert::Infallible;
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::{InputPin, OutputPin},
+};
+
+pub const REGISTER_COUNT: usize = 10;
+pub const LOG_CAPACITY: usize = 64;
+
+/// Implemented bits of each register, reserved bits read as zero.
+const REGISTER_MASKS: [u8; REGISTER_COUNT] =
+    [0xff, 0xff, 0xff, 0x7f, 0x7f, 0xff, 0xff, 0x07, 0x7f, 0x07];
+
+const ADDRESS_MASK: u8 = 0x3f;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SimulatorError {
+    ChipNotSelected,
+    InvalidLength(usize),
+    InvalidAddress(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transaction {
+    Read { address: u8, value: u8 },
+    Write { address: u8, value: u8 },
+}
+
+struct SimulatorState {
+    registers: [u8; REGISTER_COUNT],
+    chip_selected: bool,
+    chip_select_count: u32,
+    enabled: bool,
+    channel_select_high: bool,
+    state_machine_resets: u32,
+    log: [Transaction; LOG_CAPACITY],
+    log_len: usize,
+    dropped: usize,
+}
+
+impl SimulatorState {
+    fn log(&mut self, transaction: Transaction) {
+        if self.log_len < LOG_CAPACITY {
+            self.log[self.log_len] = transaction;
+            self.log_len += 1;
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    fn execute(&mut self, data: &mut [u8]) -> Result<(), SimulatorError> {
+        if !self.chip_selected {
+            return Err(SimulatorError::ChipNotSelected);
+        }
+        if data.len() != 2 {
+            return Err(SimulatorError::InvalidLength(data.len()));
+        }
+        let address = data[0] & ADDRESS_MASK;
+        if address as usize >= REGISTER_COUNT {
+            return Err(SimulatorError::InvalidAddress(address));
+        }
+        if data[0] & SPI_WRITE_BIT == SPI_WRITE_BIT {
+            let value = data[1];
+            self.log(Transaction::Write { address, value });
+            self.write_register(address, value);
+        } else {
+            let value = self.registers[address as usize];
+            self.log(Transaction::Read { address, value });
+            data[1] = value;
+        }
+        Ok(())
+    }
+
+    fn write_register(&mut self, address: u8, value: u8) {
+        if address == ConfigAddresses::ErrFlag as u8 {
+            if value & ErrorFlagsWrite::ResetAllErrorFlagsAndErrorPin as u8 != 0
+            {
+                self.registers[address as usize] = 0;
+            }
+            if value & ErrorFlagsWrite::ResetStateMachineAndMeasurement as u8
+                != 0
+            {
+                self.state_machine_resets += 1;
+            }
+        } else {
+            self.registers[address as usize] =
+                value & REGISTER_MASKS[address as usize];
+        }
+    }
+}
+
+pub struct Simulator {
+    state: RefCell<SimulatorState>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Simulator::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Simulator {
+            state: RefCell::new(SimulatorState {
+                registers: RESET_VALUES,
+                chip_selected: false,
+                chip_select_count: 0,
+                enabled: false,
+                channel_select_high: false,
+                state_machine_resets: 0,
+                log: [Transaction::Read {
+                    address: 0,
+                    value: 0,
+                }; LOG_CAPACITY],
+                log_len: 0,
+                dropped: 0,
+            }),
+        }
+    }
+
+    pub fn spi(&self) -> SimulatedSpi<'_> {
+        SimulatedSpi { simulator: self }
+    }
+
+    pub fn cs(&self) -> SimulatedCs<'_> {
+        SimulatedCs { simulator: self }
+    }
+
+    /// RESET pin, a high level restores the reset values.
+    pub fn reset_pin(&self) -> SimulatedReset<'_> {
+        SimulatedReset { simulator: self }
+    }
+
+    /// ENABLE pin, see [`Simulator::is_enabled`].
+    pub fn enable_pin(&self) -> SimulatedEnable<'_> {
+        SimulatedEnable { simulator: self }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.borrow().enabled
+    }
+
+    /// CHSEL pin, low selects channel 1 and high channel 2 if the external
+    /// channel select is enabled.
+    pub fn channel_select_pin(&self) -> SimulatedChannelSelect<'_> {
+        SimulatedChannelSelect { simulator: self }
+    }
+
+    /// ERRB pin, low while any error flag is set.
+    pub fn error_pin(&self) -> SimulatedErrb<'_> {
+        SimulatedErrb { simulator: self }
+    }
+
+    /// Channel selected by CONFIG_2 or the CHSEL pin.
+    pub fn active_channel(&self) -> ChannelSelect {
+        let state = self.state.borrow();
+        let config2 = state.registers[ConfigAddresses::Config2 as usize];
+        let channel2 = if config2 & 1 << EXTERNAL_CHANNEL_SELECT_BIT_OFFSET != 0
+        {
+            state.channel_select_high
+        } else {
+            config2 & 1 << CHANNEL_SELECT_BIT_OFFSET != 0
+        };
+        if channel2 {
+            ChannelSelect::Channel2
+        } else {
+            ChannelSelect::Channel1
+        }
+    }
+
+    pub fn register(&self, address: u8) -> u8 {
+        self.state.borrow().registers[address as usize]
+    }
+
+    pub fn registers(&self) -> [u8; REGISTER_COUNT] {
+        self.state.borrow().registers
+    }
+
+    /// Sets register bits directly, bypassing SPI and the register
+    /// semantics, e.g. to raise error flags.
+    pub fn set_register(&self, address: u8, value: u8) {
+        self.state.borrow_mut().registers[address as usize] = value;
+    }
+
+    /// Restores the reset values like a RESET pulse or power cycle.
+    pub fn reset(&self) {
+        self.state.borrow_mut().registers = RESET_VALUES;
+    }
+
+    /// Number of times the chip select was asserted.
+    pub fn chip_select_count(&self) -> u32 {
+        self.state.borrow().chip_select_count
+    }
+
+    pub fn state_machine_resets(&self) -> u32 {
+        self.state.borrow().state_machine_resets
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.state.borrow().log_len
+    }
+
+    pub fn transaction(&self, index: usize) -> Option<Transaction> {
+        let state = self.state.borrow();
+        state.log[..state.log_len].get(index).copied()
+    }
+
+    /// Transactions that did not fit into the log.
+    pub fn dropped_transactions(&self) -> usize {
+        self.state.borrow().dropped
+    }
+
+    pub fn clear_log(&self) {
+        let mut state = self.state.borrow_mut();
+        state.log_len = 0;
+        state.dropped = 0;
+    }
+}
+
+pub struct SimulatedSpi<'a> {
+    simulator: &'a Simulator,
+}
+
+impl Transfer<u8> for SimulatedSpi<'_> {
+    type Error = SimulatorError;
+
+    fn transfer<'w>(
+        &mut self,
+        words: &'w mut [u8],
+    ) -> Result<&'w [u8], SimulatorError> {
+        self.simulator.state.borrow_mut().execute(words)?;
+        Ok(words)
+    }
+}
+
+impl Write<u8> for SimulatedSpi<'_> {
+    type Error = SimulatorError;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), SimulatorError> {
+        let mut buffer = [0; 2];
+        if words.len() != buffer.len() {
+            return Err(SimulatorError::InvalidLength(words.len()));
+        }
+        buffer.copy_from_slice(words);
+        self.simulator.state.borrow_mut().execute(&mut buffer)
+    }
+}
+
+pub struct SimulatedCs<'a> {
+    simulator: &'a Simulator,
+}
+
+impl OutputPin for SimulatedCs<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        let mut state = self.simulator.state.borrow_mut();
+        if !state.chip_selected {
+            state.chip_select_count += 1;
+        }
+        state.chip_selected = true;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().chip_selected = false;
+        Ok(())
+    }
+}
+
+pub struct SimulatedReset<'a> {
+    simulator: &'a Simulator,
+}
+
+impl OutputPin for SimulatedReset<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.simulator.reset();
+        Ok(())
+    }
+}
+
+pub struct SimulatedEnable<'a> {
+    simulator: &'a Simulator,
+}
+
+impl OutputPin for SimulatedEnable<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().enabled = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().enabled = true;
+        Ok(())
+    }
+}
+
+pub struct SimulatedChannelSelect<'a> {
+    simulator: &'a Simulator,
+}
+
+impl OutputPin for SimulatedChannelSelect<'_> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().channel_select_high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.simulator.state.borrow_mut().channel_select_high = true;
+        Ok(())
+    }
+}
+
+pub struct SimulatedErrb<'a> {
+    simulator: &'a Simulator,
+}
+
+impl InputPin for SimulatedErrb<'_> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        self.is_low().map(|low| !low)
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(self.simulator.register(ConfigAddresses::ErrFlag as u8) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::simulator::{
+        Simulator, SimulatorError, Transaction, RESET_VALUES,
+    };
+    use crate::{
+        ErrNoSignalRead, ErrSignalHighRead, ErrSignalWeakRead, ErrorFlagsWrite,
+        PgaGain, Tdc1000,
+    };
+    use hal::{blocking::spi::Write, digital::v2::OutputPin};
+
+    #[test]
+    fn settings_are_written_to_all_configuration_registers() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB21);
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+
+        let mut raw_values = [0; 10];
+        tdc1000
+            .read_raw_config_values(
+                &mut simulator.cs(),
+                &mut simulator.spi(),
+                &mut raw_values,
+            )
+            .unwrap();
+        assert_eq!(raw_values[5], tdc1000.get_tof_1_value());
+        assert_eq!(raw_values[8], tdc1000.get_timeout_value());
+        assert_eq!(simulator.chip_select_count(), 19);
+        assert_eq!(
+            simulator.transaction(5),
+            Some(Transaction::Write {
+                address: 5,
+                value: tdc1000.get_tof_1_value(),
+            })
+        );
+        assert_eq!(simulator.transaction_count(), 19);
+    }
+
+    #[test]
+    fn error_flags_are_read_and_cleared() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        simulator.set_register(7, 0b101);
+        let error_flags = tdc1000
+            .read_error(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert!(
+            *error_flags.signal_week() == ErrSignalWeakRead::SignalWeekTimeout
+        );
+        assert!(*error_flags.no_signal() == ErrNoSignalRead::NoError);
+        assert!(*error_flags.signal_high() == ErrSignalHighRead::SignalHigh);
+
+        tdc1000
+            .reset_error(
+                &mut simulator.cs(),
+                &mut simulator.spi(),
+                ErrorFlagsWrite::ResetStateMachineAndMeasurement,
+            )
+            .unwrap();
+        assert_eq!(simulator.register(7), 0b101);
+        assert_eq!(simulator.state_machine_resets(), 1);
+
+        tdc1000
+            .reset_error(
+                &mut simulator.cs(),
+                &mut simulator.spi(),
+                ErrorFlagsWrite::ResetAllErrorFlagsAndErrorPin,
+            )
+            .unwrap();
+        assert_eq!(simulator.register(7), 0);
+    }
+
+    #[test]
+    fn only_changed_registers_are_written() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000
+            .write_changes(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_eq!(simulator.transaction_count(), 9);
+        assert!(!tdc1000.has_changes());
+
+        simulator.clear_log();
+        tdc1000.set_pga_gain(PgaGain::DB21);
+        tdc1000
+            .write_changes(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        tdc1000
+            .write_changes(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_eq!(simulator.transaction_count(), 1);
+        assert_eq!(
+            simulator.transaction(0),
+            Some(Transaction::Write {
+                address: 5,
+                value: tdc1000.get_tof_1_value(),
+            })
+        );
+
+        simulator.reset();
+        simulator.clear_log();
+        tdc1000.mark_all_dirty();
+        tdc1000
+            .write_changes(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_eq!(simulator.transaction_count(), 9);
+        assert_eq!(simulator.register(5), tdc1000.get_tof_1_value());
+    }
+
+    #[test]
+    fn reset_restores_defaults() {
+        let simulator = Simulator::new();
+        Tdc1000::default()
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        assert_ne!(simulator.registers(), RESET_VALUES);
+        simulator.reset();
+        assert_eq!(simulator.registers(), RESET_VALUES);
+    }
+
+    #[test]
+    fn protocol_violations_are_reported() {
+        let simulator = Simulator::new();
+        let mut spi = simulator.spi();
+        assert_eq!(spi.write(&[0x40, 0]), Err(SimulatorError::ChipNotSelected));
+
+        let mut tdc1000 = Tdc1000::default();
+        let mut raw_values = [0; 10];
+        let result = tdc1000.read_raw_config_values(
+            &mut simulator.cs(),
+            &mut spi,
+            &mut raw_values,
+        );
+        assert!(result.is_ok());
+        assert_eq!(raw_values[0], RESET_VALUES[0]);
+
+        simulator.cs().set_low().unwrap();
+        assert_eq!(
+            spi.write(&[0x4a, 0]),
+            Err(SimulatorError::InvalidAddress(0x0a))
+        );
+        assert_eq!(spi.write(&[0x40]), Err(SimulatorError::InvalidLength(1)));
+    }
+}
@@ -0,0 +1,537 @@
+//! Behavioural simulation of the acoustic channel.
+//!
+//! [`AcousticChannel`] takes the register image of a TDC1000 and produces the
+//! START and STOP edge times the chip would emit for a transducer pair
+//! coupled by a medium. The model covers:
+//!
+//! * TOF mode 0 and 2 (one way, `path_length / (c ± flow)`) and mode 1
+//!   (pulse echo, `2 * path_length / c`), channel selection and channel swap
+//! * receive gain (LNA, PGA) against the echo qualification threshold,
+//!   `SignalHigh` above 1500 mV at the comparator
+//! * blanking: echoes before `SHORT_TOF_BLANK_PERIOD * T0` (forced short TOF)
+//!   or `TIMING_REG * 8 * T0` are suppressed
+//! * the echo listening window of `TOF_TIMEOUT_CTRL * T0`, missing echoes set
+//!   `NoSignalTimeout` or `SignalWeekTimeout` if the echo timeout is enabled
+//! * single echo mode (one STOP per zero crossing at the TX frequency) and
+//!   multi echo mode (one STOP per echo, echo `k` after `(2k + 1)` path
+//!   lengths, attenuated by the echo decay)
+//! * repetition for every measurement cycle and optional uniform jitter from
+//!   a seeded pseudo random generator
+//!
+//! [`SimulatedCapture`] combines a channel with the register [`Simulator`],
+//! so driver code and algorithms can run against it end to end.
+
+use crate::measurement::{TofCapture, TofMeasurement, MAX_STOP_EVENTS};
+use crate::simulator::{Simulator, REGISTER_COUNT};
+use crate::{ConfigAddresses, ECHO_THRESHOLDS};
+use core::convert::Infallible;
+
+const LNA_GAIN_DB: f32 = 20.0;
+const SIGNAL_HIGH_MV: f32 = 1500.0;
+const ERR_SIG_HIGH: u8 = 0b001;
+const ERR_NO_SIG: u8 = 0b010;
+const ERR_SIG_WEAK: u8 = 0b100;
+/// Echoes evaluated per cycle. Bounds the echo loop for degenerate channels,
+/// e.g. a zero path length or a speed of sound that is not finite, whose
+/// echoes never leave the listening window.
+const MAX_ECHOES: u16 = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Medium {
+    /// Speed of sound in m/s.
+    pub speed_of_sound: f32,
+    /// Flow velocity in m/s in direction channel 1 to channel 2.
+    pub flow_velocity: f32,
+}
+
+impl Medium {
+    pub const WATER: Medium = Medium {
+        speed_of_sound: 1480.0,
+        flow_velocity: 0.0,
+    };
+    pub const AIR: Medium = Medium {
+        speed_of_sound: 343.0,
+        flow_velocity: 0.0,
+    };
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct EdgeTimes {
+    /// START edge in seconds after TRIGGER.
+    pub start: f32,
+    stops: [f32; MAX_STOP_EVENTS],
+    stop_count: u8,
+}
+
+impl EdgeTimes {
+    /// STOP edges in seconds after TRIGGER.
+    pub fn stops(&self) -> &[f32] {
+        &self.stops[..self.stop_count as usize]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SimulatedMeasurement {
+    /// TOF of every STOP averaged over all measurement cycles.
+    pub tof: TofMeasurement,
+    /// ERROR_FLAGS register bits raised by the measurement.
+    pub error_flags: u8,
+    pub cycles: u16,
+    pub cycle_period: f32,
+}
+
+/// Channel configuration decoded from the register image.
+struct Setup {
+    tx_period: f32,
+    cycles: u16,
+    expected_stops: u8,
+    count_stops: bool,
+    multi_echo: bool,
+    pulse_echo: bool,
+    channel_2: bool,
+    channel_swap: bool,
+    gain: f32,
+    threshold: f32,
+    blanking: f32,
+    window: f32,
+    echo_timeout: bool,
+}
+
+impl Setup {
+    fn decode(registers: &[u8; REGISTER_COUNT], clkin: f32) -> Self {
+        let register = |address: ConfigAddresses| registers[address as usize];
+        let config0 = register(ConfigAddresses::Config0);
+        let config1 = register(ConfigAddresses::Config1);
+        let config2 = register(ConfigAddresses::Config2);
+        let config3 = register(ConfigAddresses::Config3);
+        let config4 = register(ConfigAddresses::Config4);
+        let tof1 = register(ConfigAddresses::Tof1);
+        let tof0 = register(ConfigAddresses::Tof0);
+        let timeout = register(ConfigAddresses::TimeOut);
+        let clock_rate = register(ConfigAddresses::ClockRate);
+
+        let t0 = if clock_rate & 0b100 != 0 { 2.0 } else { 1.0 } / clkin;
+        let mut gain_db = 0.0;
+        if tof1 & 0b1000 == 0 {
+            gain_db += LNA_GAIN_DB;
+        }
+        if tof1 & 0b1_0000 == 0 {
+            gain_db += ((tof1 >> 5) * 3) as f32;
+        }
+        let timing_reg = ((tof1 as u16 & 0b11) << 8) | tof0 as u16;
+        let blanking = if timeout & 0b100_0000 != 0 {
+            (8 << ((timeout >> 3) & 0b111)) as f32 * t0
+        } else {
+            (timing_reg * 8) as f32 * t0
+        };
+        Setup {
+            tx_period: (2 << (config0 >> 5)) as f32 / clkin,
+            cycles: 1 << ((config1 >> 3) & 0b111),
+            expected_stops: (config1 & 0b111).max(1),
+            count_stops: config1 & 0b111 != 0,
+            multi_echo: config4 & 0b100_0000 != 0,
+            pulse_echo: config2 & 0b11 == 1,
+            channel_2: config2 & 0b100 != 0,
+            channel_swap: config2 & 0b1_0000 != 0,
+            gain: libm::powf(10.0, gain_db / 20.0),
+            threshold: ECHO_THRESHOLDS[(config3 & 0b111) as usize].millivolts()
+                as f32,
+            blanking,
+            window: (128 << (timeout & 0b11)) as f32 * t0,
+            echo_timeout: timeout & 0b100 == 0,
+        }
+    }
+}
+
+pub struct AcousticChannel {
+    clkin: f32,
+    medium: Medium,
+    path_length: f32,
+    echo_amplitude: f32,
+    echo_decay: f32,
+    jitter: f32,
+    random_state: u32,
+    swapped: bool,
+}
+
+impl AcousticChannel {
+    /// `clkin` in Hz, `path_length` in metres between the transducers or, in
+    /// pulse echo mode, between transducer and reflector.
+    pub fn new(clkin: f32, medium: Medium, path_length: f32) -> Self {
+        AcousticChannel {
+            clkin,
+            medium,
+            path_length,
+            echo_amplitude: 100.0,
+            echo_decay: 0.5,
+            jitter: 0.0,
+            random_state: 1,
+            swapped: false,
+        }
+    }
+
+    pub fn set_medium(&mut self, medium: Medium) {
+        self.medium = medium;
+    }
+
+    /// Peak amplitude of the first echo at the receiver input in mV.
+    pub fn set_echo_amplitude(&mut self, echo_amplitude: f32) {
+        self.echo_amplitude = echo_amplitude;
+    }
+
+    /// Amplitude ratio between consecutive echoes.
+    pub fn set_echo_decay(&mut self, echo_decay: f32) {
+        self.echo_decay = echo_decay;
+    }
+
+    /// Adds uniformly distributed jitter of up to `jitter` seconds to every
+    /// STOP edge. The same `seed` reproduces the same sequence.
+    pub fn set_jitter(&mut self, jitter: f32, seed: u32) {
+        self.jitter = jitter;
+        self.random_state = seed.max(1);
+    }
+
+    /// Edges of one measurement cycle and the error flags it raises.
+    pub fn cycle(
+        &mut self,
+        registers: &[u8; REGISTER_COUNT],
+        cycle: u16,
+    ) -> (EdgeTimes, u8) {
+        let setup = Setup::decode(registers, self.clkin);
+        self.simulate_cycle(&setup, cycle)
+    }
+
+    /// Runs all measurement cycles of one TRIGGER.
+    pub fn measure(
+        &mut self,
+        registers: &[u8; REGISTER_COUNT],
+    ) -> SimulatedMeasurement {
+        let setup = Setup::decode(registers, self.clkin);
+        let mut sums = [0.0; MAX_STOP_EVENTS];
+        let mut stop_count = MAX_STOP_EVENTS;
+        let mut error_flags = 0;
+        for cycle in 0..setup.cycles {
+            let (edges, flags) = self.simulate_cycle(&setup, cycle);
+            error_flags |= flags;
+            stop_count = stop_count.min(edges.stops().len());
+            for (sum, stop) in sums.iter_mut().zip(edges.stops()) {
+                *sum += stop - edges.start;
+            }
+        }
+        if setup.channel_swap {
+            self.swapped = !self.swapped;
+        }
+        let mut tof = TofMeasurement::timeout();
+        for sum in &sums[..stop_count] {
+            tof.push_stop(sum / setup.cycles as f32);
+        }
+        SimulatedMeasurement {
+            tof,
+            error_flags,
+            cycles: setup.cycles,
+            cycle_period: setup.window,
+        }
+    }
+
+    fn simulate_cycle(&mut self, setup: &Setup, cycle: u16) -> (EdgeTimes, u8) {
+        let start = cycle as f32 * setup.window;
+        let mut edges = EdgeTimes {
+            start,
+            ..EdgeTimes::default()
+        };
+        let (first_echo, echo_spacing) = self.echo_timing(setup);
+        let mut error_flags = 0;
+        let mut amplitude = self.echo_amplitude * setup.gain;
+        let mut echo: u16 = 0;
+        while edges.stop_count < setup.expected_stops && echo < MAX_ECHOES {
+            let arrival = first_echo + echo as f32 * echo_spacing;
+            if arrival > setup.window {
+                break;
+            }
+            if amplitude < setup.threshold {
+                if setup.multi_echo {
+                    break;
+                }
+                echo += 1;
+                amplitude *= self.echo_decay;
+                continue;
+            }
+            if amplitude > SIGNAL_HIGH_MV {
+                error_flags |= ERR_SIG_HIGH;
+            }
+            if arrival >= setup.blanking {
+                if setup.multi_echo {
+                    self.push_stop(&mut edges, start + arrival);
+                } else {
+                    while edges.stop_count < setup.expected_stops {
+                        let crossing =
+                            arrival + edges.stop_count as f32 * setup.tx_period;
+                        self.push_stop(&mut edges, start + crossing);
+                    }
+                }
+            }
+            echo += 1;
+            amplitude *= self.echo_decay;
+        }
+
+        if setup.echo_timeout {
+            if edges.stop_count == 0 {
+                error_flags |= ERR_NO_SIG;
+            } else if setup.count_stops
+                && edges.stop_count < setup.expected_stops
+            {
+                error_flags |= ERR_SIG_WEAK;
+            }
+        }
+        (edges, error_flags)
+    }
+
+    /// Arrival of the first echo after START and the spacing of further
+    /// echoes in the echo train.
+    fn echo_timing(&self, setup: &Setup) -> (f32, f32) {
+        let speed_of_sound = self.medium.speed_of_sound;
+        let echo_spacing = 2.0 * self.path_length / speed_of_sound;
+        if setup.pulse_echo {
+            return (echo_spacing, echo_spacing);
+        }
+        let channel_2 = setup.channel_2 != self.swapped;
+        let speed = if channel_2 {
+            speed_of_sound - self.medium.flow_velocity
+        } else {
+            speed_of_sound + self.medium.flow_velocity
+        };
+        (self.path_length / speed, echo_spacing)
+    }
+
+    fn push_stop(&mut self, edges: &mut EdgeTimes, stop: f32) {
+        let jitter = self.jitter * self.next_random();
+        edges.stops[edges.stop_count as usize] = stop + jitter;
+        edges.stop_count += 1;
+    }
+
+    /// Uniformly distributed value in -1..1 (xorshift32).
+    fn next_random(&mut self) -> f32 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state = x;
+        (x as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+/// [`TofCapture`] running the acoustic channel against the registers of a
+/// [`Simulator`] and raising its error flags.
+pub struct SimulatedCapture<'a> {
+    simulator: &'a Simulator,
+    channel: AcousticChannel,
+}
+
+impl<'a> SimulatedCapture<'a> {
+    pub fn new(simulator: &'a Simulator, channel: AcousticChannel) -> Self {
+        SimulatedCapture { simulator, channel }
+    }
+
+    pub fn channel_mut(&mut self) -> &mut AcousticChannel {
+        &mut self.channel
+    }
+}
+
+impl TofCapture for SimulatedCapture<'_> {
+    type Error = Infallible;
+
+    fn capture(&mut self) -> Result<TofMeasurement, Infallible> {
+        let measurement = self.channel.measure(&self.simulator.registers());
+        let address = ConfigAddresses::ErrFlag as u8;
+        self.simulator.set_register(
+            address,
+            self.simulator.register(address) | measurement.error_flags,
+        );
+        Ok(measurement.tof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::measurement::TofCapture;
+    use crate::simulator::acoustic::{
+        AcousticChannel, Medium, SimulatedCapture,
+    };
+    use crate::simulator::Simulator;
+    use crate::{
+        ChannelSwap, EchoTimeout, ErrNoSignalRead, ErrSignalWeakRead,
+        ForceShortTimeOfFlight, MeasurementCycles, ReceiveEventsCnt,
+        ReceiveMode, ShortTofBlankPeriod, TOFMeasurementMode, Tdc1000,
+        TofTimeoutControl,
+    };
+
+    const CLKIN: f32 = 8_000_000.0;
+    const PATH_LENGTH: f32 = 0.05;
+
+    fn registers(tdc1000: &mut Tdc1000) -> [u8; 10] {
+        let simulator = Simulator::new();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.registers()
+    }
+
+    fn tdc1000() -> Tdc1000 {
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_tof_timeout_ctrl(TofTimeoutControl::T0Times1024);
+        tdc1000
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 1e-9,
+            "{} is not close to {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn stop_follows_start_after_tof() {
+        let mut channel =
+            AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH);
+        let (edges, error_flags) = channel.cycle(&registers(&mut tdc1000()), 2);
+        assert_eq!(error_flags, 0);
+        assert_close(edges.start, 2.0 * 1024.0 / CLKIN);
+        assert_close(edges.stops()[0] - edges.start, PATH_LENGTH / 1480.0);
+    }
+
+    #[test]
+    fn multi_echo_train_and_cycles_are_simulated() {
+        let mut tdc1000 = tdc1000();
+        tdc1000.set_tof_meas_mode(TOFMeasurementMode::Mode1);
+        tdc1000.set_receive_mode(ReceiveMode::MultiEcho);
+        tdc1000.set_receive_events(ReceiveEventsCnt::StopEvents3);
+        tdc1000.set_measurement_cycles(MeasurementCycles::MeasurementCycles4);
+        let mut channel =
+            AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH / 2.0);
+        channel.set_echo_amplitude(100.0);
+        let measurement = channel.measure(&registers(&mut tdc1000));
+        let round_trip = PATH_LENGTH / 1480.0;
+        assert_eq!(measurement.cycles, 4);
+        assert_eq!(measurement.tof.stops().len(), 3);
+        assert_close(measurement.tof.stops()[0], round_trip);
+        assert_close(measurement.tof.stops()[2], 3.0 * round_trip);
+        assert_eq!(measurement.error_flags, 0);
+    }
+
+    #[test]
+    fn weak_echo_train_raises_signal_weak() {
+        let mut tdc1000 = tdc1000();
+        tdc1000.set_receive_mode(ReceiveMode::MultiEcho);
+        tdc1000.set_receive_events(ReceiveEventsCnt::StopEvents3);
+        let mut channel =
+            AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH);
+        channel.set_echo_amplitude(30.0);
+        channel.set_echo_decay(0.5);
+        let measurement = channel.measure(&registers(&mut tdc1000));
+        assert_eq!(measurement.tof.stops().len(), 2);
+        assert_eq!(measurement.error_flags, 0b100);
+    }
+
+    #[test]
+    fn degenerate_channels_terminate() {
+        let registers = registers(&mut tdc1000());
+        let mut channel = AcousticChannel::new(CLKIN, Medium::WATER, 0.0);
+        channel.set_echo_amplitude(0.0);
+        let (edges, error_flags) = channel.cycle(&registers, 0);
+        assert!(edges.stops().is_empty());
+        assert_eq!(error_flags, 0b010);
+
+        let medium = Medium {
+            speed_of_sound: f32::NAN,
+            flow_velocity: 0.0,
+        };
+        let mut channel = AcousticChannel::new(CLKIN, medium, PATH_LENGTH);
+        let (edges, error_flags) = channel.cycle(&registers, 0);
+        assert!(edges.stops().is_empty());
+        assert_eq!(error_flags, 0b010);
+    }
+
+    #[test]
+    fn blanking_and_timeout_suppress_echoes() {
+        let mut tdc1000 = tdc1000();
+        tdc1000.set_force_short_tof(
+            ForceShortTimeOfFlight::ForceShortTimeOfFlight,
+        );
+        tdc1000.set_short_tof_blank_period(ShortTofBlankPeriod::T0Times1024);
+        let mut channel =
+            AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH);
+        let measurement = channel.measure(&registers(&mut tdc1000));
+        assert!(measurement.tof.is_timeout());
+        assert_eq!(measurement.error_flags, 0b010);
+
+        tdc1000.set_echo_timeout(EchoTimeout::DisableTimeout);
+        let measurement = channel.measure(&registers(&mut tdc1000));
+        assert_eq!(measurement.error_flags, 0);
+
+        let mut channel = AcousticChannel::new(CLKIN, Medium::AIR, PATH_LENGTH);
+        let measurement = channel.measure(&registers(&mut self::tdc1000()));
+        assert!(measurement.tof.is_timeout());
+    }
+
+    #[test]
+    fn capture_alternates_direction_with_channel_swap() {
+        let mut tdc1000 = tdc1000();
+        tdc1000.set_tof_meas_mode(TOFMeasurementMode::Mode2);
+        tdc1000.set_channel_swap(ChannelSwap::EnableSwap);
+        let simulator = Simulator::new();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        let medium = Medium {
+            speed_of_sound: 1480.0,
+            flow_velocity: 2.0,
+        };
+        let mut capture = SimulatedCapture::new(
+            &simulator,
+            AcousticChannel::new(CLKIN, medium, PATH_LENGTH),
+        );
+        let forward = capture.capture().unwrap().first_stop().unwrap();
+        let reverse = capture.capture().unwrap().first_stop().unwrap();
+        assert_close(forward, PATH_LENGTH / 1482.0);
+        assert_close(reverse, PATH_LENGTH / 1478.0);
+    }
+
+    #[test]
+    fn capture_raises_error_flags_in_registers() {
+        let simulator = Simulator::new();
+        let mut tdc1000 = tdc1000();
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        let mut capture = SimulatedCapture::new(
+            &simulator,
+            AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH),
+        );
+        capture.channel_mut().set_echo_amplitude(1.0);
+        let measurement = tdc1000
+            .measure(&mut simulator.cs(), &mut simulator.spi(), &mut capture)
+            .unwrap();
+        assert!(measurement.is_timeout());
+        let error_flags = measurement.error_flags().unwrap();
+        assert!(*error_flags.no_signal() == ErrNoSignalRead::NoSignalTimeout);
+        assert!(*error_flags.signal_week() == ErrSignalWeakRead::NoError);
+        assert_eq!(simulator.register(7), 0);
+    }
+
+    #[test]
+    fn jitter_is_reproducible() {
+        let registers = registers(&mut tdc1000());
+        let mut first = AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH);
+        let mut second =
+            AcousticChannel::new(CLKIN, Medium::WATER, PATH_LENGTH);
+        first.set_jitter(1e-9, 42);
+        second.set_jitter(1e-9, 42);
+        let stop = first.measure(&registers).tof.first_stop().unwrap();
+        assert_eq!(stop, second.measure(&registers).tof.first_stop().unwrap());
+        assert!((stop - PATH_LENGTH / 1480.0).abs() <= 1e-9);
+        assert_ne!(stop, first.measure(&registers).tof.first_stop().unwrap());
+    }
+}
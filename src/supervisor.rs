@@ -0,0 +1,158 @@
+//! Measurements that recover from a stuck state machine.
+//!
+//! A missing STOP pulse or a glitch on TRIGGER can leave the TDC1000 state
+//! machine waiting for an event that never comes. The supervisor has no
+//! timeout of its own: the [`TofCapture`] implementation has to detect this
+//! through a bounded wait and return [`TofMeasurement::timeout`], see
+//! [`TofCapture::capture`]. [`Tdc1000::measure`] then resets the state
+//! machine and the error flags and [`MeasurementSupervisor`] retries the
+//! measurement.
+
+use crate::measurement::{MeasureError, TofCapture, TofMeasurement};
+use crate::trace::SpiTrace;
+use crate::{Error, Tdc1000};
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryStatistics {
+    /// Calls of [`MeasurementSupervisor::measure`].
+    pub measurements: u32,
+    /// State machine resets, one per timed out attempt.
+    pub resets: u32,
+    /// Measurements that succeeded after at least one retry.
+    pub recovered: u32,
+    /// Measurements that timed out on every attempt.
+    pub failed: u32,
+}
+
+pub struct MeasurementSupervisor {
+    max_retries: u8,
+    statistics: RetryStatistics,
+}
+
+impl Default for MeasurementSupervisor {
+    fn default() -> Self {
+        MeasurementSupervisor {
+            max_retries: 3,
+            statistics: RetryStatistics::default(),
+        }
+    }
+}
+
+impl MeasurementSupervisor {
+    /// Attempts after the first timed out measurement.
+    pub fn set_max_retries(&mut self, retries: u8) {
+        self.max_retries = retries;
+    }
+
+    pub fn statistics(&self) -> &RetryStatistics {
+        &self.statistics
+    }
+
+    pub fn reset_statistics(&mut self) {
+        self.statistics = RetryStatistics::default();
+    }
+
+    /// Like [`Tdc1000::measure`], which resets the state machine after a
+    /// timed out measurement, but retries timed out measurements. Fails with
+    /// [`Error::MeasurementTimeout`] if all attempts timed out.
+    pub fn measure<T, CS, SPI, C, CsE, SpiE>(
+        &mut self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+    ) -> Result<TofMeasurement, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        self.statistics.measurements =
+            self.statistics.measurements.saturating_add(1);
+        for attempt in 0..=self.max_retries {
+            let measurement = tdc1000.measure(cs, spi, capture)?;
+            if !measurement.is_timeout() {
+                if attempt > 0 {
+                    self.statistics.recovered =
+                        self.statistics.recovered.saturating_add(1);
+                }
+                return Ok(measurement);
+            }
+            self.statistics.resets = self.statistics.resets.saturating_add(1);
+        }
+        self.statistics.failed = self.statistics.failed.saturating_add(1);
+        Err(MeasureError::DriverError(Error::MeasurementTimeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::measurement::MeasureError;
+    use crate::supervisor::{MeasurementSupervisor, RetryStatistics};
+    use crate::test_support::{FakeCapture, FakeCs, FakeFrontEnd, FakeSpi};
+    use crate::{Error, Tdc1000};
+
+    /// Every third measurement succeeds.
+    fn model(_registers: &[u8; 10], measurements: u32) -> (Option<f32>, u8) {
+        if measurements % 3 == 2 {
+            (Some(20e-6), 0)
+        } else {
+            (None, 0b10)
+        }
+    }
+
+    #[test]
+    fn stuck_measurements_are_retried() {
+        let front_end = FakeFrontEnd::new(model);
+        let mut supervisor = MeasurementSupervisor::default();
+        let measurement = supervisor
+            .measure(
+                &mut Tdc1000::default(),
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+            )
+            .unwrap();
+        assert_eq!(measurement.first_stop(), Some(20e-6));
+        assert_eq!(front_end.borrow().state_machine_resets, 2);
+        // Only the combined reset, no separate error flag reset.
+        assert_eq!(front_end.borrow().error_resets, 2);
+        assert_eq!(
+            *supervisor.statistics(),
+            RetryStatistics {
+                measurements: 1,
+                resets: 2,
+                recovered: 1,
+                failed: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn exhausted_retries_report_a_timeout() {
+        let front_end = FakeFrontEnd::new(model);
+        let mut supervisor = MeasurementSupervisor::default();
+        supervisor.set_max_retries(1);
+        let result = supervisor.measure(
+            &mut Tdc1000::default(),
+            &mut FakeCs,
+            &mut FakeSpi(&front_end),
+            &mut FakeCapture(&front_end),
+        );
+        assert_eq!(
+            result,
+            Err(MeasureError::DriverError(Error::MeasurementTimeout))
+        );
+        assert_eq!(supervisor.statistics().failed, 1);
+        assert_eq!(front_end.borrow().state_machine_resets, 2);
+        assert_eq!(front_end.borrow().registers[7], 0);
+    }
+}
@@ -0,0 +1,338 @@
+//! Setup wizard sweeping PGA gain, echo threshold, TX pulses and damping.
+//!
+//! Every point of the grid is written to the device and measured several
+//! times. Points are ranked by their success rate first, then by the number
+//! of measurements with error flags and finally by the TOF jitter.
+
+use crate::measurement::{MeasureError, MeasurementStatistics, TofCapture};
+use crate::trace::SpiTrace;
+use crate::{
+    DampingMode, EchoQualificationThreshold, PgaGain, Tdc1000, TxPulses,
+};
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+pub struct SweepGrid<'a> {
+    pub gains: &'a [PgaGain],
+    pub thresholds: &'a [EchoQualificationThreshold],
+    pub tx_pulses: &'a [TxPulses],
+    pub damping: &'a [DampingMode],
+}
+
+impl SweepGrid<'_> {
+    pub fn len(&self) -> usize {
+        self.gains.len()
+            * self.thresholds.len()
+            * self.tx_pulses.len()
+            * self.damping.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SweepPoint {
+    pub gain: PgaGain,
+    pub threshold: EchoQualificationThreshold,
+    pub tx_pulses: TxPulses,
+    pub damping: DampingMode,
+}
+
+impl SweepPoint {
+    fn from_config<T>(tdc1000: &Tdc1000<T>) -> Self {
+        SweepPoint {
+            gain: tdc1000.amplifier_and_time_of_flight.pga_gain,
+            threshold: tdc1000.config3.echo_qualification_threshold,
+            tx_pulses: tdc1000.config0.tx_pulses,
+            damping: tdc1000.config2.damping_mode,
+        }
+    }
+
+    pub fn apply<T: SpiTrace>(&self, tdc1000: &mut Tdc1000<T>) {
+        tdc1000.set_pga_gain(self.gain);
+        tdc1000.set_echo_qualification_threshold(self.threshold);
+        tdc1000.set_number_of_tx_pulses(self.tx_pulses);
+        tdc1000.set_damping(self.damping);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SweepResult {
+    pub point: SweepPoint,
+    pub statistics: MeasurementStatistics,
+}
+
+impl SweepResult {
+    pub fn is_better_than(&self, other: &SweepResult) -> bool {
+        let success_rate = self.statistics.success_rate();
+        let other_success_rate = other.statistics.success_rate();
+        if success_rate != other_success_rate {
+            return success_rate > other_success_rate;
+        }
+        let erroneous = self.statistics.errors.erroneous;
+        let other_erroneous = other.statistics.errors.erroneous;
+        if erroneous != other_erroneous {
+            return erroneous < other_erroneous;
+        }
+        match (self.statistics.tof.jitter(), other.statistics.tof.jitter()) {
+            (Some(jitter), Some(other_jitter)) => jitter < other_jitter,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct SetupWizard<'a> {
+    grid: SweepGrid<'a>,
+    measurements_per_point: u16,
+}
+
+impl<'a> SetupWizard<'a> {
+    pub fn new(grid: SweepGrid<'a>, measurements_per_point: u16) -> Self {
+        SetupWizard {
+            grid,
+            measurements_per_point,
+        }
+    }
+
+    /// Runs the sweep and stores the result of every grid point in
+    /// `results`, as far as it has room for them. The best point is applied
+    /// to `tdc1000` and written to the device. If the grid is empty, no point
+    /// had a successful measurement or the sweep fails, the original settings
+    /// are restored. An empty grid or a sweep without successes returns
+    /// `None`.
+    pub fn run<T, CS, SPI, C, CsE, SpiE>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+        results: &mut [SweepResult],
+    ) -> Result<Option<SweepResult>, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let original = SweepPoint::from_config(tdc1000);
+        let best = match self.sweep(tdc1000, cs, spi, capture, results) {
+            Ok(best) => best.filter(|best| best.statistics.successes > 0),
+            Err(error) => {
+                original.apply(tdc1000);
+                // The sweep error is more useful than a failed restore.
+                let _ = tdc1000.write_settings(cs, spi);
+                return Err(error);
+            }
+        };
+        best.map_or(original, |best| best.point).apply(tdc1000);
+        tdc1000.write_settings(cs, spi)?;
+        Ok(best)
+    }
+
+    fn sweep<T, CS, SPI, C, CsE, SpiE>(
+        &self,
+        tdc1000: &mut Tdc1000<T>,
+        cs: &mut CS,
+        spi: &mut SPI,
+        capture: &mut C,
+        results: &mut [SweepResult],
+    ) -> Result<Option<SweepResult>, MeasureError<CsE, SpiE, C::Error>>
+    where
+        T: SpiTrace,
+        CS: OutputPin<Error = CsE>,
+        SPI: Transfer<u8, Error = SpiE> + Write<u8, Error = SpiE>,
+        C: TofCapture,
+    {
+        let mut best: Option<SweepResult> = None;
+        let mut index = 0;
+        for damping in self.grid.damping {
+            for tx_pulses in self.grid.tx_pulses {
+                for threshold in self.grid.thresholds {
+                    for gain in self.grid.gains {
+                        let point = SweepPoint {
+                            gain: *gain,
+                            threshold: *threshold,
+                            tx_pulses: *tx_pulses,
+                            damping: *damping,
+                        };
+                        point.apply(tdc1000);
+                        tdc1000.write_settings(cs, spi)?;
+                        let result = SweepResult {
+                            point,
+                            statistics: MeasurementStatistics::collect(
+                                tdc1000,
+                                cs,
+                                spi,
+                                capture,
+                                self.measurements_per_point,
+                            )?,
+                        };
+                        if let Some(slot) = results.get_mut(index) {
+                            *slot = result;
+                        }
+                        index += 1;
+                        let is_best = match best {
+                            Some(best) => result.is_better_than(&best),
+                            None => true,
+                        };
+                        if is_best {
+                            best = Some(result);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::measurement::MeasureError;
+    use crate::sweep::{SetupWizard, SweepGrid, SweepResult};
+    use crate::test_support::{
+        FailingCapture, FakeCapture, FakeCs, FakeFrontEnd, FakeSpi,
+    };
+    use crate::{
+        DampingMode, EchoQualificationThreshold, PgaGain, Tdc1000, TxPulses,
+    };
+
+    // Only receives an echo with at least 9 dB PGA gain and produces less
+    // jitter with more TX pulses.
+    fn model(registers: &[u8; 10], measurements: u32) -> (Option<f32>, u8) {
+        if registers[5] >> 5 < PgaGain::DB9 as u8 {
+            return (None, 0b10);
+        }
+        let pulses = (registers[0] & 0x1f) as f32;
+        let noise = if measurements & 1 == 0 { 1e-7 } else { -1e-7 };
+        (Some(1e-4 + noise / pulses), 0)
+    }
+
+    #[test]
+    fn best_point_is_selected_and_written() {
+        let front_end = FakeFrontEnd::new(model);
+        let gains = [PgaGain::DB0, PgaGain::DB9, PgaGain::DB21];
+        let pulses = [TxPulses::new_const(2), TxPulses::new_const(10)];
+        let grid = SweepGrid {
+            gains: &gains,
+            thresholds: &[EchoQualificationThreshold::Mv125],
+            tx_pulses: &pulses,
+            damping: &[DampingMode::DisableDamping],
+        };
+        let mut results = [SweepResult::default(); 6];
+        let mut tdc1000 = Tdc1000::default();
+        let best = SetupWizard::new(grid, 4)
+            .run(
+                &mut tdc1000,
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+                &mut results,
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(best.point.gain as u8, PgaGain::DB9 as u8);
+        assert_eq!(best.point.tx_pulses.get_value(), 10);
+        assert_eq!(results[0].statistics.errors.no_signal, 4);
+        assert_eq!(results[1].statistics.successes, 4);
+        let registers = front_end.borrow().registers;
+        assert_eq!(registers[0], tdc1000.get_config_0_value());
+        assert_eq!(registers[5] >> 5, PgaGain::DB9 as u8);
+    }
+
+    #[test]
+    fn sweep_without_successes_restores_original_settings() {
+        let front_end = FakeFrontEnd::new(|_, _| (None, 0b10));
+        let gains = [PgaGain::DB0, PgaGain::DB21];
+        let grid = SweepGrid {
+            gains: &gains,
+            thresholds: &[EchoQualificationThreshold::Mv125],
+            tx_pulses: &[TxPulses::new_const(2)],
+            damping: &[DampingMode::DisableDamping],
+        };
+        let mut results = [SweepResult::default(); 2];
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB12);
+        let best = SetupWizard::new(grid, 4)
+            .run(
+                &mut tdc1000,
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+                &mut results,
+            )
+            .unwrap();
+        assert!(best.is_none());
+        assert_eq!(results[1].statistics.errors.no_signal, 4);
+        assert_eq!(front_end.borrow().registers[5] >> 5, PgaGain::DB12 as u8);
+        assert_eq!(
+            tdc1000.amplifier_and_time_of_flight.pga_gain as u8,
+            PgaGain::DB12 as u8
+        );
+    }
+
+    #[test]
+    fn empty_grid_restores_original_settings() {
+        let front_end = FakeFrontEnd::new(model);
+        let grid = SweepGrid {
+            gains: &[],
+            thresholds: &[EchoQualificationThreshold::Mv125],
+            tx_pulses: &[TxPulses::new_const(2)],
+            damping: &[DampingMode::DisableDamping],
+        };
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB12);
+        let best = SetupWizard::new(grid, 4)
+            .run(
+                &mut tdc1000,
+                &mut FakeCs,
+                &mut FakeSpi(&front_end),
+                &mut FakeCapture(&front_end),
+                &mut [],
+            )
+            .unwrap();
+        assert!(best.is_none());
+        assert_eq!(front_end.borrow().registers[5] >> 5, PgaGain::DB12 as u8);
+    }
+
+    #[test]
+    fn failed_sweep_restores_original_settings() {
+        let front_end = FakeFrontEnd::new(model);
+        let grid = SweepGrid {
+            gains: &[PgaGain::DB0, PgaGain::DB21],
+            thresholds: &[EchoQualificationThreshold::Mv125],
+            tx_pulses: &[TxPulses::new_const(2)],
+            damping: &[DampingMode::DisableDamping],
+        };
+        let mut tdc1000 = Tdc1000::default();
+        tdc1000.set_pga_gain(PgaGain::DB12);
+        let original = tdc1000.clone();
+        let result = SetupWizard::new(grid, 4).run(
+            &mut tdc1000,
+            &mut FakeCs,
+            &mut FakeSpi(&front_end),
+            &mut FailingCapture {
+                capture: FakeCapture(&front_end),
+                remaining: 6,
+            },
+            &mut [],
+        );
+        assert_eq!(result, Err(MeasureError::CaptureError(())));
+        assert_eq!(tdc1000.get_tof_1_value(), original.get_tof_1_value());
+        assert_eq!(tdc1000.get_config_0_value(), original.get_config_0_value());
+        let registers = front_end.borrow().registers;
+        assert_eq!(registers[0], original.get_config_0_value());
+        assert_eq!(registers[5] >> 5, PgaGain::DB12 as u8);
+    }
+}
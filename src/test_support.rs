@@ -0,0 +1,111 @@
+use crate::measurement::{TofCapture, TofMeasurement};
+use core::cell::RefCell;
+use core::convert::Infallible;
+use hal::{
+    blocking::spi::{Transfer, Write},
+    digital::v2::OutputPin,
+};
+
+const ERROR_FLAGS_ADDRESS: usize = 7;
+
+/// Register file plus a model deciding the outcome of each measurement from
+/// the register values and the number of previous measurements.
+pub struct FakeFrontEnd {
+    pub registers: [u8; 10],
+    pub measurements: u32,
+    /// Writes to ERROR_FLAGS.
+    pub error_resets: u32,
+    pub state_machine_resets: u32,
+    model: fn(&[u8; 10], u32) -> (Option<f32>, u8),
+}
+
+impl FakeFrontEnd {
+    pub fn new(
+        model: fn(&[u8; 10], u32) -> (Option<f32>, u8),
+    ) -> RefCell<Self> {
+        RefCell::new(FakeFrontEnd {
+            registers: [0; 10],
+            measurements: 0,
+            error_resets: 0,
+            state_machine_resets: 0,
+            model,
+        })
+    }
+}
+
+pub struct FakeSpi<'a>(pub &'a RefCell<FakeFrontEnd>);
+pub struct FakeCs;
+pub struct FakeCapture<'a>(pub &'a RefCell<FakeFrontEnd>);
+
+impl Write<u8> for FakeSpi<'_> {
+    type Error = Infallible;
+    fn write(&mut self, data: &[u8]) -> Result<(), Infallible> {
+        let mut front_end = self.0.borrow_mut();
+        let address = (data[0] & 0x3f) as usize;
+        if address == ERROR_FLAGS_ADDRESS {
+            front_end.error_resets += 1;
+            if data[1] & 0b1 == 0b1 {
+                front_end.registers[ERROR_FLAGS_ADDRESS] = 0;
+            }
+            if data[1] & 0b10 == 0b10 {
+                front_end.state_machine_resets += 1;
+            }
+        } else {
+            front_end.registers[address] = data[1];
+        }
+        Ok(())
+    }
+}
+
+impl Transfer<u8> for FakeSpi<'_> {
+    type Error = Infallible;
+    fn transfer<'w>(
+        &mut self,
+        words: &'w mut [u8],
+    ) -> Result<&'w [u8], Infallible> {
+        words[1] = self.0.borrow().registers[(words[0] & 0x3f) as usize];
+        Ok(words)
+    }
+}
+
+impl OutputPin for FakeCs {
+    type Error = Infallible;
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl TofCapture for FakeCapture<'_> {
+    type Error = Infallible;
+    fn capture(&mut self) -> Result<TofMeasurement, Infallible> {
+        let mut front_end = self.0.borrow_mut();
+        let (tof, error_flags) =
+            (front_end.model)(&front_end.registers, front_end.measurements);
+        front_end.measurements += 1;
+        front_end.registers[ERROR_FLAGS_ADDRESS] |= error_flags;
+        Ok(match tof {
+            Some(tof) => TofMeasurement::new(&[tof]),
+            None => TofMeasurement::timeout(),
+        })
+    }
+}
+
+/// Fails once `remaining` captures succeeded.
+pub struct FailingCapture<'a> {
+    pub capture: FakeCapture<'a>,
+    pub remaining: u8,
+}
+
+impl TofCapture for FailingCapture<'_> {
+    type Error = ();
+    fn capture(&mut self) -> Result<TofMeasurement, ()> {
+        if self.remaining == 0 {
+            return Err(());
+        }
+        self.remaining -= 1;
+        Ok(self.capture.capture().unwrap())
+    }
+}
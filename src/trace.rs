@@ -0,0 +1,85 @@
+//! Observer for register accesses.
+//!
+//! A [`SpiTrace`] passed to [`Tdc1000::with_trace`](crate::Tdc1000::with_trace)
+//! is called after every successful register read or write. The default
+//! [`NoTrace`] does nothing and is optimised away.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+pub trait SpiTrace {
+    fn on_access(&mut self, address: u8, direction: Direction, data: u8);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoTrace;
+
+impl SpiTrace for NoTrace {
+    #[inline(always)]
+    fn on_access(&mut self, _address: u8, _direction: Direction, _data: u8) {}
+}
+
+/// Logs every register access with `defmt::trace!`.
+#[cfg(feature = "defmt")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DefmtTrace;
+
+#[cfg(feature = "defmt")]
+impl SpiTrace for DefmtTrace {
+    fn on_access(&mut self, address: u8, direction: Direction, data: u8) {
+        defmt::trace!(
+            "TDC1000 {} {=u8:#04x}: {=u8:#04x}",
+            direction,
+            address,
+            data
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::simulator::Simulator;
+    use crate::trace::{Direction, SpiTrace};
+    use crate::Tdc1000;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct RecordingTrace(Vec<(u8, Direction, u8)>);
+
+    impl SpiTrace for RecordingTrace {
+        fn on_access(&mut self, address: u8, direction: Direction, data: u8) {
+            self.0.push((address, direction, data));
+        }
+    }
+
+    #[test]
+    fn register_accesses_are_traced() {
+        let simulator = Simulator::new();
+        let mut tdc1000 =
+            Tdc1000::default().with_trace(RecordingTrace::default());
+        tdc1000
+            .write_settings(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+        simulator.set_register(7, 0b10);
+        tdc1000
+            .read_error(&mut simulator.cs(), &mut simulator.spi())
+            .unwrap();
+
+        let accesses = &tdc1000.tracer().0;
+        assert_eq!(accesses.len(), 10);
+        assert_eq!(
+            accesses[0],
+            (0, Direction::Write, tdc1000.get_config_0_value())
+        );
+        assert_eq!(accesses[8], (9, Direction::Write, 0));
+        assert_eq!(accesses[9], (7, Direction::Read, 0b10));
+    }
+}